@@ -0,0 +1,448 @@
+//!AUTH 3D stage, camera and object animation.
+//!
+//!A3DA files are sorted `key=value` lines such as
+//!
+//!```text
+//!#A3DA__________
+//!camera_root.0.view_point.trans.x.key.0.data=(0,1.5,0,0)
+//!camera_root.0.view_point.trans.x.key.0.type=3
+//!camera_root.0.view_point.trans.x.key.length=1
+//!camera_root.0.view_point.trans.x.type=3
+//!play_control.fps=60
+//!play_control.size=360
+//!```
+//!
+//![`A3da`] keeps every property so files read back unchanged, and [`A3da::curve`] turns the
+//!ones under a path into an [`A3daCurve`]. The binary A3DC files aren't read or written.
+use super::*;
+use crate::curve::sample_split_keys;
+
+use core::fmt;
+use core::str::FromStr;
+
+#[derive(Clone, PartialEq, Eq, Debug, Error)]
+pub enum A3daError {
+    #[error("Not an A3DA file")]
+    NotA3da,
+    #[error("Line {0} is not a `key=value` pair")]
+    BadLine(usize),
+    #[error("`{0}` has a malformed value")]
+    BadValue(String),
+    #[error("`{0}` has unknown curve type {1}")]
+    UnknownType(String, u8),
+}
+
+const A3DA_MAGIC: &str = "#A3DA__________";
+
+///The incoming and outgoing slope of a key, per frame
+pub type Tangents = (f32, f32);
+
+///A curve of an A3DA file, keyed on the same [`Keyframe`]s as [`FrameData`]
+#[derive(Clone, PartialEq, Debug)]
+pub enum A3daCurve {
+    ///Type 0, always zero
+    None,
+    ///Type 1
+    Static(f32),
+    ///Type 2, straight lines between keys
+    Linear(Vec<Keyframe>),
+    ///Type 3
+    Hermite(Vec<Keyframe<Tangents>>),
+    ///Type 4, each key holding until the next
+    Hold(Vec<Keyframe>),
+}
+
+impl A3daCurve {
+    pub fn evaluate(&self, frame: f32) -> f32 {
+        match self {
+            A3daCurve::None => 0.,
+            A3daCurve::Static(x) => *x,
+            A3daCurve::Linear(keys) => sample_split_keys(keys, frame, |keys, i| {
+                let slope = |a: &Keyframe, b: &Keyframe| {
+                    (b.value - a.value) / (b.frame as f32 - a.frame as f32).max(1.)
+                };
+                let incoming = i.checked_sub(1).map_or(0., |p| slope(&keys[p], &keys[i]));
+                let outgoing = keys.get(i + 1).map_or(0., |n| slope(&keys[i], n));
+                (incoming, outgoing)
+            })
+            .map_or(0., |(x, _)| x),
+            A3daCurve::Hermite(keys) => {
+                sample_split_keys(keys, frame, |keys, i| keys[i].interpolation)
+                    .map_or(0., |(x, _)| x)
+            }
+            A3daCurve::Hold(keys) => keys
+                .iter()
+                .take_while(|k| k.frame as f32 <= frame)
+                .last()
+                .or_else(|| keys.first())
+                .map_or(0., |k| k.value),
+        }
+    }
+
+    ///Converts to [`FrameData`], fitting the curves it has no exact match for to `tolerance`
+    pub fn to_frame_data(&self, tolerance: f32) -> FrameData {
+        let keys = match self {
+            A3daCurve::None => return FrameData::None,
+            A3daCurve::Static(x) => return FrameData::Pose(*x),
+            A3daCurve::Hermite(keys)
+                if keys.iter().all(|k| k.interpolation.0 == k.interpolation.1) =>
+            {
+                return FrameData::Hermite(
+                    keys.iter()
+                        .map(|k| Keyframe {
+                            interpolation: k.interpolation.0,
+                            frame: k.frame,
+                            value: k.value,
+                        })
+                        .collect(),
+                )
+            }
+            A3daCurve::Linear(keys) | A3daCurve::Hold(keys) => {
+                keys.iter().map(|k| k.frame).collect()
+            }
+            A3daCurve::Hermite(keys) => keys.iter().map(|k| k.frame).collect::<Vec<_>>(),
+        };
+        let last = keys.last().copied().unwrap_or_default();
+        let samples: Vec<f32> = (0..=last).map(|f| self.evaluate(f as f32)).collect();
+        FrameData::fit(0, &samples, tolerance)
+    }
+}
+
+impl From<&FrameData> for A3daCurve {
+    fn from(data: &FrameData) -> Self {
+        match data {
+            FrameData::None => A3daCurve::None,
+            FrameData::Pose(x) => A3daCurve::Static(*x),
+            FrameData::CatmulRom(_) => (&data.clone().into_hermite()).into(),
+            FrameData::Hermite(keys) => A3daCurve::Hermite(
+                keys.iter()
+                    .map(|k| Keyframe {
+                        frame: k.frame,
+                        value: k.value,
+                        interpolation: (k.interpolation, k.interpolation),
+                    })
+                    .collect(),
+            ),
+        }
+    }
+}
+
+///The properties of an A3DA file
+#[derive(Clone, PartialEq, Eq, Debug, Default)]
+pub struct A3da {
+    pub properties: BTreeMap<String, String>,
+}
+
+fn parse<T: FromStr>(key: &str, value: &str) -> Result<T, A3daError> {
+    value
+        .trim()
+        .parse()
+        .map_err(|_| A3daError::BadValue(key.to_string()))
+}
+
+///The numbers of a key's `(frame,value,...)` tuple
+fn parse_tuple(key: &str, value: &str) -> Result<Vec<f32>, A3daError> {
+    let inner = value
+        .trim()
+        .strip_prefix('(')
+        .and_then(|x| x.strip_suffix(')'))
+        .ok_or_else(|| A3daError::BadValue(key.to_string()))?;
+    inner.split(',').map(|x| parse(key, x)).collect()
+}
+
+impl A3da {
+    pub fn parse(text: &str) -> Result<Self, A3daError> {
+        let mut lines = text.lines();
+        match lines.next() {
+            Some(magic) if magic.starts_with("#A3DA") => (),
+            _ => return Err(A3daError::NotA3da),
+        }
+        let mut properties = BTreeMap::new();
+        for (i, line) in lines.enumerate() {
+            if line.starts_with('#') || line.trim().is_empty() {
+                continue;
+            }
+            let eq = line.find('=').ok_or(A3daError::BadLine(i + 2))?;
+            properties.insert(line[..eq].to_string(), line[eq + 1..].to_string());
+        }
+        Ok(Self { properties })
+    }
+
+    pub fn get(&self, key: &str) -> Option<&str> {
+        self.properties.get(key).map(|x| &x[..])
+    }
+
+    pub fn set(&mut self, key: &str, value: impl ToString) {
+        self.properties.insert(key.to_string(), value.to_string());
+    }
+
+    fn number<T: FromStr>(&self, key: &str) -> Result<Option<T>, A3daError> {
+        self.get(key).map(|x| parse(key, x)).transpose()
+    }
+
+    pub fn fps(&self) -> Result<Option<f32>, A3daError> {
+        self.number("play_control.fps")
+    }
+
+    pub fn frames(&self) -> Result<Option<f32>, A3daError> {
+        self.number("play_control.size")
+    }
+
+    ///Reads the curve at `path`, e.g. `camera_root.0.view_point.trans.x`, if there is one.
+    ///
+    ///Key frames are rounded to the nearest whole frame to fit [`Keyframe`], and frames that
+    ///round to outside of `0..=u16::MAX` are a [`A3daError::BadValue`].
+    pub fn curve(&self, path: &str) -> Result<Option<A3daCurve>, A3daError> {
+        let key = |k: &str| format!("{}.{}", path, k);
+        let ty: u8 = match self.number(&key("type"))? {
+            Some(ty) => ty,
+            None => return Ok(None),
+        };
+        //Keys are either one property each or a flat list in `raw_data`
+        let mut tuples = vec![];
+        if let Some(list) = self.get(&key("raw_data.value_list")) {
+            let width = match self.number::<usize>(&key("raw_data_key_type"))? {
+                Some(n) if n > 0 => n + 1,
+                _ => 2,
+            };
+            let values = list
+                .split(',')
+                .map(|x| parse(&key("raw_data.value_list"), x))
+                .collect::<Result<Vec<f32>, _>>()?;
+            tuples.extend(values.chunks(width).map(|x| x.to_vec()));
+        } else {
+            let len = match self.number::<usize>(&key("key.length"))? {
+                Some(len) => len,
+                None if self.get(&key("key.0.data")).is_some() => {
+                    return Err(A3daError::BadValue(key("key.length")))
+                }
+                None => 0,
+            };
+            for i in 0..len {
+                let data = key(&format!("key.{}.data", i));
+                match self.get(&data) {
+                    Some(value) => tuples.push(parse_tuple(&data, value)?),
+                    None => return Err(A3daError::BadValue(data)),
+                }
+            }
+        }
+        let at = |t: &[f32], i: usize| t.get(i).copied().unwrap_or_default();
+        let frame = |t: &[f32]| match at(t, 0).round() {
+            f if (0. ..=u16::MAX as f32).contains(&f) => Ok(f as u16),
+            _ => Err(A3daError::BadValue(key("key"))),
+        };
+        let plain = || {
+            tuples
+                .iter()
+                .map(|t| {
+                    Ok(Keyframe {
+                        frame: frame(t)?,
+                        value: at(t, 1),
+                        interpolation: (),
+                    })
+                })
+                .collect::<Result<_, A3daError>>()
+        };
+        let curve = match ty {
+            0 => A3daCurve::None,
+            1 => A3daCurve::Static(self.number(&key("value"))?.unwrap_or_default()),
+            2 => A3daCurve::Linear(plain()?),
+            3 => A3daCurve::Hermite(
+                tuples
+                    .iter()
+                    .map(|t| {
+                        let tangent = at(t, 2);
+                        Ok(Keyframe {
+                            frame: frame(t)?,
+                            value: at(t, 1),
+                            interpolation: (tangent, t.get(3).copied().unwrap_or(tangent)),
+                        })
+                    })
+                    .collect::<Result<_, A3daError>>()?,
+            ),
+            4 => A3daCurve::Hold(plain()?),
+            _ => return Err(A3daError::UnknownType(path.to_string(), ty)),
+        };
+        Ok(Some(curve))
+    }
+
+    ///Replaces the curve at `path`, leaving the other properties under it alone
+    pub fn set_curve(&mut self, path: &str, curve: &A3daCurve) {
+        let prefix = format!("{}.", path);
+        self.properties.retain(|k, _| {
+            !(k.starts_with(&prefix)
+                && (k[prefix.len()..].starts_with("key.")
+                    || k[prefix.len()..].starts_with("raw_data")
+                    || matches!(&k[prefix.len()..], "type" | "value")))
+        });
+        let key = |k: &str| format!("{}{}", prefix, k);
+        let mut set_keys = |keys: Vec<Vec<f32>>| {
+            for (i, t) in keys.iter().enumerate() {
+                let values: Vec<String> = t.iter().map(|x| x.to_string()).collect();
+                self.set(
+                    &key(&format!("key.{}.data", i)),
+                    format!("({})", values.join(",")),
+                );
+                self.set(&key(&format!("key.{}.type", i)), t.len() - 1);
+            }
+            self.set(&key("key.length"), keys.len());
+        };
+        let plain = |keys: &[Keyframe]| {
+            keys.iter()
+                .map(|k| vec![k.frame as f32, k.value])
+                .collect::<Vec<_>>()
+        };
+        let ty = match curve {
+            A3daCurve::None => 0,
+            A3daCurve::Static(_) => 1,
+            A3daCurve::Linear(keys) => {
+                set_keys(plain(keys));
+                2
+            }
+            A3daCurve::Hermite(keys) => {
+                set_keys(
+                    keys.iter()
+                        .map(|k| match k.interpolation {
+                            (a, b) if a == 0. && b == 0. => vec![k.frame as f32, k.value],
+                            (a, b) if a == b => vec![k.frame as f32, k.value, a],
+                            (a, b) => vec![k.frame as f32, k.value, a, b],
+                        })
+                        .collect(),
+                );
+                3
+            }
+            A3daCurve::Hold(keys) => {
+                set_keys(plain(keys));
+                4
+            }
+        };
+        if let A3daCurve::Static(x) = curve {
+            self.set(&key("value"), x);
+        }
+        self.set(&key("type"), ty);
+    }
+}
+
+impl fmt::Display for A3da {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "{}", A3DA_MAGIC)?;
+        for (key, value) in &self.properties {
+            writeln!(f, "{}={}", key, value)?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const CAMERA: &str = "#A3DA__________
+#Tue Jan 01 00:00:00 2013
+camera_root.0.view_point.trans.x.key.0.data=(0,1,0,0.5)
+camera_root.0.view_point.trans.x.key.0.type=3
+camera_root.0.view_point.trans.x.key.1.data=(10,2)
+camera_root.0.view_point.trans.x.key.1.type=1
+camera_root.0.view_point.trans.x.key.length=2
+camera_root.0.view_point.trans.x.max=10
+camera_root.0.view_point.trans.x.type=3
+camera_root.0.view_point.trans.y.type=1
+camera_root.0.view_point.trans.y.value=1.4
+camera_root.0.view_point.trans.z.raw_data.value_list=0,0,5,1
+camera_root.0.view_point.trans.z.raw_data.value_list_size=4
+camera_root.0.view_point.trans.z.raw_data.value_type=float
+camera_root.0.view_point.trans.z.raw_data_key_type=1
+camera_root.0.view_point.trans.z.type=2
+camera_root.length=1
+play_control.fps=60
+play_control.size=11
+";
+
+    #[test]
+    fn read_curves() -> anyhow::Result<()> {
+        let a3da = A3da::parse(CAMERA)?;
+        assert_eq!(a3da.fps()?, Some(60.));
+        let x = a3da.curve("camera_root.0.view_point.trans.x")?.unwrap();
+        assert_eq!(
+            x,
+            A3daCurve::Hermite(vec![
+                Keyframe {
+                    frame: 0,
+                    value: 1.,
+                    interpolation: (0., 0.5),
+                },
+                Keyframe {
+                    frame: 10,
+                    value: 2.,
+                    interpolation: (0., 0.),
+                },
+            ])
+        );
+        let y = a3da.curve("camera_root.0.view_point.trans.y")?.unwrap();
+        assert_eq!(y.to_frame_data(1e-4), FrameData::Pose(1.4));
+        let z = a3da.curve("camera_root.0.view_point.trans.z")?.unwrap();
+        assert!((z.evaluate(2.5) - 0.5).abs() < 1e-6);
+        assert_eq!(a3da.curve("camera_root.0.view_point.rot.x")?, None);
+        Ok(())
+    }
+
+    #[test]
+    fn text_roundtrip() -> anyhow::Result<()> {
+        let mut a3da = A3da::parse(CAMERA)?;
+        let curve = A3daCurve::from(&FrameData::CatmulRom(vec![
+            Keyframe {
+                frame: 0,
+                value: 0.,
+                interpolation: (),
+            },
+            Keyframe {
+                frame: 5,
+                value: 0.75,
+                interpolation: (),
+            },
+        ]));
+        a3da.set_curve("camera_root.0.view_point.trans.z", &curve);
+        assert_eq!(
+            a3da.get("camera_root.0.view_point.trans.z.raw_data_key_type"),
+            None
+        );
+        assert_eq!(
+            a3da.get("camera_root.0.view_point.trans.z.key.1.data"),
+            Some("(5,0.75,0.15)")
+        );
+
+        let text = a3da.to_string();
+        assert_eq!(A3da::parse(&text)?, a3da);
+        assert_eq!(
+            A3da::parse(&text)?.curve("camera_root.0.view_point.trans.z")?,
+            Some(curve)
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn reject_bad_keys() {
+        let mut a3da = A3da::default();
+        a3da.set("obj.type", 2);
+        a3da.set("obj.key.0.data", "(0,1)");
+        assert_eq!(
+            a3da.curve("obj"),
+            Err(A3daError::BadValue("obj.key.length".into()))
+        );
+        a3da.set("obj.key.length", 1);
+        a3da.set("obj.key.0.data", "(-3,1)");
+        assert_eq!(
+            a3da.curve("obj"),
+            Err(A3daError::BadValue("obj.key".into()))
+        );
+        a3da.set("obj.key.0.data", "(2.4,1)");
+        assert_eq!(
+            a3da.curve("obj"),
+            Ok(Some(A3daCurve::Linear(vec![Keyframe {
+                frame: 2,
+                value: 1.,
+                interpolation: (),
+            }])))
+        );
+    }
+}
@@ -0,0 +1,313 @@
+use super::*;
+use crate::channel::{Axis, Component};
+use crate::math::{self, Quat, Vector3};
+
+///Samples all three axes of a component, treating missing data as 0
+pub(crate) fn sample(vec: &Vec3, frame: f32) -> Vector3 {
+    let mut out = [0.; 3];
+    for (i, &axis) in Axis::ALL.iter().enumerate() {
+        out[i] = axis.get(vec).evaluate(frame).unwrap_or_default();
+    }
+    out
+}
+
+///Fits one curve per axis through per frame samples starting at `start`
+pub(crate) fn fit(start: u16, samples: &[Vector3], tolerance: f32) -> Vec3 {
+    let axis = |i: usize| {
+        let samples: Vec<f32> = samples.iter().map(|x| x[i]).collect();
+        FrameData::fit(start, &samples, tolerance)
+    };
+    (axis(0), axis(1), axis(2))
+}
+
+///Turns per frame rotations back into Euler samples that don't wrap between frames
+pub(crate) fn to_euler(rotations: &[Quat]) -> Vec<Vector3> {
+    let mut prev = [0.; 3];
+    rotations
+        .iter()
+        .map(|q| {
+            prev = math::unwrap_euler(q.to_euler(), prev);
+            prev
+        })
+        .collect()
+}
+
+///Combines `a` sampled from `a_start` with `b` sampled from `b_start` over `count` frames.
+///
+///Rotations are combined as quaternions through `rotation`, every other component through
+///`vector`. Both get the local frame as their last argument.
+fn combine<R, V>(
+    (a, a_start): (&BoneAnim, u16),
+    (b, b_start): (&BoneAnim, u16),
+    count: u16,
+    rotation: R,
+    vector: V,
+    tolerance: f32,
+) -> Option<BoneAnim>
+where
+    R: Fn(Quat, Quat, u16) -> Quat,
+    V: Fn(Vector3, Vector3, u16) -> Vector3,
+{
+    if core::mem::discriminant(a) != core::mem::discriminant(b) {
+        return None;
+    }
+    let others = b.components();
+    let mut out = a.clone();
+    for (component, vec) in out.components_mut() {
+        //Same variant, so the component is always present
+        let (_, other) = others.iter().find(|(c, _)| *c == component).unwrap();
+        let pairs = (0..count).map(|i| {
+            let x = sample(vec, (a_start + i) as f32);
+            let y = sample(other, (b_start + i) as f32);
+            (x, y, i)
+        });
+        let samples: Vec<Vector3> = if component == Component::Rotation {
+            let rotations: Vec<Quat> = pairs
+                .map(|(x, y, i)| rotation(Quat::from_euler(x), Quat::from_euler(y), i))
+                .collect();
+            to_euler(&rotations)
+        } else {
+            pairs.map(|(x, y, i)| vector(x, y, i)).collect()
+        };
+        *vec = fit(0, &samples, tolerance);
+    }
+    Some(out)
+}
+
+///Mixes `a` with `b`, where `weight` gives the share of `b` on each local frame
+fn mix<W>(
+    a: (&BoneAnim, u16),
+    b: (&BoneAnim, u16),
+    count: u16,
+    weight: W,
+    tolerance: f32,
+) -> Option<BoneAnim>
+where
+    W: Fn(u16) -> f32,
+{
+    combine(
+        a,
+        b,
+        count,
+        |x, y, i| x.slerp(y, weight(i)),
+        |x, y, i| math::lerp(x, y, weight(i)),
+        tolerance,
+    )
+}
+
+///An animation of the same type where every channel is missing, i.e. sampled as 0
+fn zeroed(anim: &BoneAnim) -> BoneAnim {
+    anim.map(|_, _, _| FrameData::None)
+}
+
+impl<'a> Motion<'a> {
+    ///Blends `self` with `other`, where a `weight` of 0 is `self` and 1 is `other`.
+    ///
+    ///Bones animated by only one of the motions keep that animation. Bones whose animation
+    ///types differ take the animation of whichever motion has the larger weight.
+    pub fn blend(&self, other: &Motion<'a>, weight: f32, tolerance: f32) -> Self {
+        let frames = self.frames.max(other.frames);
+        let mut anims = self.anims.clone();
+        for (bone, b) in &other.anims {
+            let b = match b {
+                Some(b) => b,
+                None => continue,
+            };
+            let blended = match self.anims.get(bone) {
+                Some(Some(a)) => mix((a, 0), (b, 0), frames, |_| weight, tolerance)
+                    .unwrap_or_else(|| if weight < 0.5 { a.clone() } else { b.clone() }),
+                _ => b.clone(),
+            };
+            anims.insert(bone.clone(), Some(blended));
+        }
+        Self { frames, anims }
+    }
+
+    ///Plays `self` then `other`, overlapping the last `frames` frames of `self` with the first
+    ///`frames` frames of `other` and easing from one into the other.
+    ///
+    ///Bones animated by only one of the motions behave as in [`Motion::append`].
+    pub fn crossfade(&self, other: &Motion<'a>, frames: u16, tolerance: f32) -> Self {
+        let frames = frames.min(self.frames).min(other.frames);
+        let start = self.frames - frames;
+        //Smoothstep so both motions are entered and left without a jump in velocity
+        let ease = |i: u16| {
+            let t = (i as f32 + 1.) / (frames as f32 + 1.);
+            t * t * (3. - 2. * t)
+        };
+        let mut mot = self.trim(0..start);
+        let mut tail = other.clone();
+        tail.offset(-(frames as i32));
+
+        let mut window = Motion {
+            frames,
+            anims: BTreeMap::new(),
+        };
+        for (bone, a) in &self.anims {
+            let a = match a {
+                Some(a) => a,
+                None => continue,
+            };
+            let mixed = match other.anims.get(bone) {
+                Some(Some(b)) => mix((a, start), (b, 0), frames, ease, tolerance),
+                _ => None,
+            };
+            let anim = mixed.unwrap_or_else(|| a.map(|_, _, d| d.shifted(-(start as i32))));
+            window.anims.insert(bone.clone(), Some(anim));
+        }
+        for (bone, b) in &other.anims {
+            if let (Some(b), None) = (b, window.anims.get(bone)) {
+                window.anims.insert(bone.clone(), Some(b.clone()));
+            }
+        }
+
+        mot.append(&window);
+        mot.append(&tail);
+        mot
+    }
+
+    ///Freezes `frame` into a single frame motion of constant poses
+    pub fn pose_at(&self, frame: u16) -> Self {
+        let mut mot = self.clone();
+        for (_, data) in mot.channels_mut() {
+            if let Some(value) = data.evaluate(frame as f32) {
+                *data = FrameData::Pose(value);
+            }
+        }
+        mot.frames = 1;
+        mot
+    }
+
+    ///Computes the additive layer that turns `reference` into `self`.
+    ///
+    ///A reference shorter than `self` holds its last frame, so a motion from
+    ///[`Motion::pose_at`] acts as a reference pose. Rotations are stored as the local rotation
+    ///applied after the reference, every other component as an offset.
+    pub fn difference(&self, reference: &Motion<'a>, tolerance: f32) -> Self {
+        let frames = self.frames;
+        let mut anims = BTreeMap::new();
+        for (bone, anim) in &self.anims {
+            let anim = match anim {
+                Some(anim) => anim,
+                None => {
+                    anims.insert(bone.clone(), None);
+                    continue;
+                }
+            };
+            let base = match reference.anims.get(bone) {
+                Some(Some(base))
+                    if core::mem::discriminant(base) == core::mem::discriminant(anim) =>
+                {
+                    base.clone()
+                }
+                _ => zeroed(anim),
+            };
+            let layer = combine(
+                (anim, 0),
+                (&base, 0),
+                frames,
+                |x, y, _| y.conjugate() * x,
+                |x, y, _| math::sub(x, y),
+                tolerance,
+            );
+            anims.insert(bone.clone(), layer);
+        }
+        Self { frames, anims }
+    }
+
+    ///Applies an additive `layer` from [`Motion::difference`] on top of `self`, scaled by
+    ///`weight`. Bones only found in the layer are applied on top of a zero pose.
+    pub fn apply_additive(&self, layer: &Motion<'a>, weight: f32, tolerance: f32) -> Self {
+        let frames = self.frames;
+        let mut anims = self.anims.clone();
+        for (bone, diff) in &layer.anims {
+            let diff = match diff {
+                Some(diff) => diff,
+                None => continue,
+            };
+            let base = match self.anims.get(bone) {
+                Some(Some(base)) => base.clone(),
+                _ => zeroed(diff),
+            };
+            let applied = combine(
+                (&base, 0),
+                (diff, 0),
+                frames,
+                |x, y, _| x * Quat::IDENTITY.slerp(y, weight),
+                |x, y, _| math::add(x, math::scale(y, weight)),
+                tolerance,
+            );
+            if let Some(applied) = applied {
+                anims.insert(bone.clone(), Some(applied));
+            }
+        }
+        Self { frames, anims }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::channel::ChannelPath;
+
+    fn motion(x: f32, rot: f32) -> Motion<'static> {
+        let mut anims = BTreeMap::new();
+        let pos = (FrameData::Pose(x), FrameData::Pose(0.), FrameData::Pose(0.));
+        let rotation = (
+            FrameData::Pose(0.),
+            FrameData::Pose(rot),
+            FrameData::Pose(0.),
+        );
+        anims.insert(
+            Bone("n_hara_cp".into()),
+            Some(BoneAnim::PositionRotation {
+                position: pos,
+                rotation,
+            }),
+        );
+        Motion { frames: 30, anims }
+    }
+
+    fn value(mot: &Motion, path: &str, frame: f32) -> f32 {
+        let path = ChannelPath::parse(path).unwrap();
+        mot.get(&path).unwrap().evaluate(frame).unwrap()
+    }
+
+    #[test]
+    fn blend_halfway() {
+        let a = motion(0., 3.);
+        let b = motion(2., -3.);
+        let mot = a.blend(&b, 0.5, 1e-4);
+        assert!((value(&mot, "n_hara_cp.position.x", 0.) - 1.).abs() < 1e-4);
+        //Halfway between 3 and -3 the short way round is a half turn, not 0
+        let y = value(&mot, "n_hara_cp.rotation.y", 0.).abs();
+        assert!((y - core::f32::consts::PI).abs() < 1e-3);
+    }
+
+    #[test]
+    fn additive_roundtrip() {
+        let base = motion(1., 0.5);
+        let target = motion(3., 0.75);
+        let layer = target.difference(&base.pose_at(0), 1e-5);
+        assert!((value(&layer, "n_hara_cp.position.x", 0.) - 2.).abs() < 1e-4);
+        assert!((value(&layer, "n_hara_cp.rotation.y", 0.) - 0.25).abs() < 1e-4);
+
+        let full = base.apply_additive(&layer, 1., 1e-5);
+        assert!((value(&full, "n_hara_cp.position.x", 10.) - 3.).abs() < 1e-4);
+        assert!((value(&full, "n_hara_cp.rotation.y", 10.) - 0.75).abs() < 1e-4);
+        let half = base.apply_additive(&layer, 0.5, 1e-5);
+        assert!((value(&half, "n_hara_cp.position.x", 10.) - 2.).abs() < 1e-4);
+    }
+
+    #[test]
+    fn crossfade_eases() {
+        let a = motion(0., 0.);
+        let b = motion(10., 0.);
+        let mot = a.crossfade(&b, 10, 1e-4);
+        assert_eq!(mot.frames(), 50);
+        assert!(value(&mot, "n_hara_cp.position.x", 19.).abs() < 1e-4);
+        let mid = value(&mot, "n_hara_cp.position.x", 25.);
+        assert!(mid > 0. && mid < 10.);
+        assert!((value(&mot, "n_hara_cp.position.x", 30.) - 10.).abs() < 1e-4);
+    }
+}
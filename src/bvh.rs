@@ -0,0 +1,759 @@
+use super::*;
+use crate::blend::{fit, sample, to_euler};
+use crate::ik::align;
+use crate::math::{self, Quat, Transform, Vector3};
+use crate::retarget::BoneMap;
+use crate::retime::TempoMap;
+use crate::skeleton::Skeleton;
+use core::fmt;
+use diva_db::bone::BoneType;
+
+#[derive(Clone, PartialEq, Eq, PartialOrd, Ord, Debug, Error)]
+pub enum BvhError {
+    #[error("Expected `{expected}` but found `{found}`")]
+    Unexpected { expected: String, found: String },
+    #[error("File ended early")]
+    UnexpectedEnd,
+    #[error("Invalid number `{0}`")]
+    BadNumber(String),
+    #[error("Unknown channel `{0}`")]
+    UnknownChannel(String),
+    #[error("Frame {0} doesn't have a value for every channel")]
+    ShortFrame(usize),
+    #[error("{0} frames don't fit in a motion")]
+    TooLong(usize),
+    #[error("Can't resample to the frame rate given")]
+    BadFrameRate,
+    #[error("The frame time isn't a positive number of seconds")]
+    BadFrameTime,
+}
+
+#[derive(Copy, Clone, PartialEq, Eq, PartialOrd, Ord, Hash, Debug)]
+pub enum BvhChannel {
+    Xposition,
+    Yposition,
+    Zposition,
+    Xrotation,
+    Yrotation,
+    Zrotation,
+}
+
+impl BvhChannel {
+    fn parse(s: &str) -> Result<Self, BvhError> {
+        use BvhChannel::*;
+        Ok(match s {
+            "Xposition" => Xposition,
+            "Yposition" => Yposition,
+            "Zposition" => Zposition,
+            "Xrotation" => Xrotation,
+            "Yrotation" => Yrotation,
+            "Zrotation" => Zrotation,
+            _ => return Err(BvhError::UnknownChannel(s.to_string())),
+        })
+    }
+
+    fn axis(self) -> usize {
+        use BvhChannel::*;
+        match self {
+            Xposition | Xrotation => 0,
+            Yposition | Yrotation => 1,
+            Zposition | Zrotation => 2,
+        }
+    }
+
+    fn is_position(self) -> bool {
+        use BvhChannel::*;
+        matches!(self, Xposition | Yposition | Zposition)
+    }
+}
+
+impl fmt::Display for BvhChannel {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fmt::Debug::fmt(self, f)
+    }
+}
+
+#[derive(Clone, PartialEq, Debug)]
+pub struct BvhJoint {
+    pub name: String,
+    pub parent: Option<usize>,
+    pub offset: Vector3,
+    pub channels: Vec<BvhChannel>,
+    pub end_site: Option<Vector3>,
+}
+
+///A Biovision Hierarchy file
+#[derive(Clone, PartialEq, Debug, Default)]
+pub struct Bvh {
+    ///Joints with parents always coming before their children
+    pub joints: Vec<BvhJoint>,
+    ///Seconds per frame
+    pub frame_time: f32,
+    ///Values for every channel of every joint in order, one list per frame
+    pub frames: Vec<Vec<f32>>,
+}
+
+struct Tokens<'a>(core::str::SplitWhitespace<'a>);
+
+impl<'a> Tokens<'a> {
+    fn next(&mut self) -> Result<&'a str, BvhError> {
+        self.0.next().ok_or(BvhError::UnexpectedEnd)
+    }
+
+    fn expect(&mut self, expected: &str) -> Result<(), BvhError> {
+        match self.next()? {
+            x if x.eq_ignore_ascii_case(expected) => Ok(()),
+            x => Err(BvhError::Unexpected {
+                expected: expected.to_string(),
+                found: x.to_string(),
+            }),
+        }
+    }
+
+    fn number<T: core::str::FromStr>(&mut self) -> Result<T, BvhError> {
+        let x = self.next()?;
+        x.parse().map_err(|_| BvhError::BadNumber(x.to_string()))
+    }
+
+    fn vector(&mut self) -> Result<Vector3, BvhError> {
+        Ok([self.number()?, self.number()?, self.number()?])
+    }
+}
+
+impl Bvh {
+    pub fn parse(text: &str) -> Result<Self, BvhError> {
+        let mut tokens = Tokens(text.split_whitespace());
+        tokens.expect("HIERARCHY")?;
+        let mut bvh = Bvh::default();
+        //The joint each open brace belongs to, `None` for end sites
+        let mut stack: Vec<Option<usize>> = vec![];
+        loop {
+            let token = tokens.next()?;
+            match token {
+                "ROOT" | "JOINT" => {
+                    let name = tokens.next()?.to_string();
+                    tokens.expect("{")?;
+                    tokens.expect("OFFSET")?;
+                    let offset = tokens.vector()?;
+                    tokens.expect("CHANNELS")?;
+                    let count: usize = tokens.number()?;
+                    let channels = (0..count)
+                        .map(|_| BvhChannel::parse(tokens.next()?))
+                        .collect::<Result<_, _>>()?;
+                    let parent = stack.iter().rev().flatten().next().copied();
+                    bvh.joints.push(BvhJoint {
+                        name,
+                        parent,
+                        offset,
+                        channels,
+                        end_site: None,
+                    });
+                    stack.push(Some(bvh.joints.len() - 1));
+                }
+                "End" => {
+                    tokens.expect("Site")?;
+                    tokens.expect("{")?;
+                    tokens.expect("OFFSET")?;
+                    let offset = tokens.vector()?;
+                    if let Some(Some(joint)) = stack.last() {
+                        bvh.joints[*joint].end_site = Some(offset);
+                    }
+                    stack.push(None);
+                }
+                "}" => {
+                    stack.pop();
+                }
+                "MOTION" if stack.is_empty() => break,
+                x => {
+                    return Err(BvhError::Unexpected {
+                        expected: "JOINT".to_string(),
+                        found: x.to_string(),
+                    })
+                }
+            }
+        }
+        tokens.expect("Frames:")?;
+        let frames: usize = tokens.number()?;
+        tokens.expect("Frame")?;
+        tokens.expect("Time:")?;
+        bvh.frame_time = tokens.number()?;
+        let channels: usize = bvh.joints.iter().map(|j| j.channels.len()).sum();
+        for i in 0..frames {
+            let frame = (0..channels)
+                .map(|_| match tokens.next() {
+                    Err(_) => Err(BvhError::ShortFrame(i)),
+                    Ok(x) => x.parse().map_err(|_| BvhError::BadNumber(x.to_string())),
+                })
+                .collect::<Result<_, _>>()?;
+            bvh.frames.push(frame);
+        }
+        Ok(bvh)
+    }
+
+    ///The local transform of every joint on `frame`
+    pub fn local(&self, frame: usize) -> Vec<Transform> {
+        let mut values = self.frames[frame].iter();
+        self.joints
+            .iter()
+            .map(|joint| {
+                let mut translation = joint.offset;
+                let mut rotation = Quat::IDENTITY;
+                for &channel in &joint.channels {
+                    let value = values.next().copied().unwrap_or_default();
+                    let axis = channel.axis();
+                    if channel.is_position() {
+                        translation[axis] = value;
+                    } else {
+                        let mut dir = [0.; 3];
+                        dir[axis] = 1.;
+                        rotation = rotation * Quat::from_axis_angle(dir, value.to_radians());
+                    }
+                }
+                Transform::new(translation, rotation.normalize())
+            })
+            .collect()
+    }
+
+    ///The world transform of every joint on `frame`
+    pub fn world(&self, frame: usize) -> Vec<Transform> {
+        let local = self.local(frame);
+        let mut world: Vec<Transform> = Vec::with_capacity(local.len());
+        for (joint, &local) in self.joints.iter().zip(&local) {
+            world.push(match joint.parent {
+                Some(p) => world[p] * local,
+                None => local,
+            });
+        }
+        world
+    }
+
+    fn write_joint(&self, f: &mut fmt::Formatter<'_>, i: usize, depth: usize) -> fmt::Result {
+        let joint = &self.joints[i];
+        let tabs = "\t".repeat(depth);
+        let kind = if joint.parent.is_none() {
+            "ROOT"
+        } else {
+            "JOINT"
+        };
+        let [x, y, z] = joint.offset;
+        writeln!(f, "{}{} {}", tabs, kind, joint.name)?;
+        writeln!(f, "{}{{", tabs)?;
+        writeln!(f, "{}\tOFFSET {} {} {}", tabs, x, y, z)?;
+        write!(f, "{}\tCHANNELS {}", tabs, joint.channels.len())?;
+        for channel in &joint.channels {
+            write!(f, " {}", channel)?;
+        }
+        writeln!(f)?;
+        for child in (0..self.joints.len()).filter(|&c| self.joints[c].parent == Some(i)) {
+            self.write_joint(f, child, depth + 1)?;
+        }
+        if let Some([x, y, z]) = joint.end_site {
+            writeln!(f, "{}\tEnd Site", tabs)?;
+            writeln!(f, "{}\t{{", tabs)?;
+            writeln!(f, "{}\t\tOFFSET {} {} {}", tabs, x, y, z)?;
+            writeln!(f, "{}\t}}", tabs)?;
+        }
+        writeln!(f, "{}}}", tabs)
+    }
+}
+
+impl fmt::Display for Bvh {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "HIERARCHY")?;
+        for root in (0..self.joints.len()).filter(|&i| self.joints[i].parent.is_none()) {
+            self.write_joint(f, root, 0)?;
+        }
+        writeln!(f, "MOTION")?;
+        writeln!(f, "Frames: {}", self.frames.len())?;
+        writeln!(f, "Frame Time: {}", self.frame_time)?;
+        for frame in &self.frames {
+            let line: Vec<String> = frame.iter().map(|x| x.to_string()).collect();
+            writeln!(f, "{}", line.join(" "))?;
+        }
+        Ok(())
+    }
+}
+
+impl BoneMap {
+    ///Maps the joint names used by most mocap rigs onto the joints of [`Skeleton::without_ik`].
+    ///
+    ///Namespaced names like Mixamo's `mixamorig:Hips` are matched by [`Motion::from_bvh`] without
+    ///their prefix, so each bone has a single name and the map can be inverted for export.
+    pub fn mocap() -> Self {
+        let mut map = BoneMap::new();
+        map.insert("Hips", "n_hara_cp");
+        map.insert("Spine", "j_mune_wj");
+        map.insert("Neck", "kl_kubi");
+        map.insert("Head", "j_kao_wj");
+        for &(side, l) in [("Left", "l"), ("Right", "r")].iter() {
+            let name = |x: &str| x.replace("{}", l);
+            map.insert(&format!("{}Shoulder", side), &name("kl_waki_{}_wj"));
+            map.insert(&format!("{}Arm", side), &name("j_kata_{}_wj_cu"));
+            map.insert(&format!("{}ForeArm", side), &name("j_ude_{}_wj"));
+            map.insert(&format!("{}Hand", side), &name("kl_te_{}_wj"));
+            map.insert(&format!("{}UpLeg", side), &name("j_momo_{}_wj"));
+            map.insert(&format!("{}Leg", side), &name("j_sune_{}_wj"));
+            map.insert(&format!("{}Foot", side), &name("kl_asi_{}_wj_co"));
+            map.insert(&format!("{}ToeBase", side), &name("kl_toe_{}_wj"));
+            let fingers = [
+                ("Thumb", "oya"),
+                ("Index", "hito"),
+                ("Middle", "naka"),
+                ("Ring", "kusu"),
+                ("Pinky", "ko"),
+            ];
+            for &(finger, diva) in fingers.iter() {
+                for (n, part) in ["", "_b", "_c"].iter().enumerate() {
+                    map.insert(
+                        &format!("{}Hand{}{}", side, finger, n + 1),
+                        &format!("nl_{}{}_{}_wj", diva, part, l),
+                    );
+                }
+            }
+        }
+        map
+    }
+}
+
+fn euler_degrees(rotation: &Vec3, frame: f32) -> [f32; 3] {
+    let [x, y, z] = sample(rotation, frame);
+    [x.to_degrees(), y.to_degrees(), z.to_degrees()]
+}
+
+impl<'a> Motion<'a> {
+    ///Imports `bvh` onto `skeleton`, renaming joints through `map` and multiplying positions by
+    ///`scale`.
+    ///
+    ///Each mapped joint is rotated relative to its closest mapped ancestor, so joints without a
+    ///Diva counterpart fold into their children. Joints whose full name isn't in `map` are looked
+    ///up again without a `prefix:` namespace. Rotations are retargeted through the rest poses:
+    ///every bone is turned so it points from its rest position towards its mapped children the
+    ///way the joint does in the rig's rest pose, which lets T-pose rigs drive the Diva skeleton.
+    ///The result is resampled from the file's frame time, which has to be positive, to `fps`
+    ///and IK chains are rebuilt with [`Motion::to_ik`].
+    pub fn from_bvh(
+        bvh: &Bvh,
+        map: &BoneMap,
+        skeleton: &Skeleton,
+        fps: f32,
+        scale: f32,
+        tolerance: f32,
+    ) -> Result<Self, BvhError> {
+        if bvh.frames.len() > u16::MAX as usize {
+            return Err(BvhError::TooLong(bvh.frames.len()));
+        }
+        if !(bvh.frame_time.is_finite() && bvh.frame_time > 0.) {
+            return Err(BvhError::BadFrameTime);
+        }
+        let fk = skeleton.without_ik();
+        let mapped: Vec<Option<usize>> = bvh
+            .joints
+            .iter()
+            .map(|j| {
+                let name = j.name.rsplit(':').next().unwrap_or_default();
+                fk.find(map.get(&j.name)).or_else(|| fk.find(map.get(name)))
+            })
+            .collect();
+        let ancestor = |mut i: usize| loop {
+            match bvh.joints[i].parent {
+                Some(p) if mapped[p].is_some() => return Some(p),
+                Some(p) => i = p,
+                None => return None,
+            }
+        };
+        let parents: Vec<Option<usize>> = (0..bvh.joints.len()).map(ancestor).collect();
+
+        //Where every joint and bone sits at rest
+        let mut rig_rest: Vec<Vector3> = Vec::with_capacity(bvh.joints.len());
+        for joint in &bvh.joints {
+            let parent = joint.parent.map_or([0.; 3], |p| rig_rest[p]);
+            rig_rest.push(math::add(parent, joint.offset));
+        }
+        let bone_rest = |mut i: usize| {
+            let mut position = [0.; 3];
+            loop {
+                position = math::add(position, fk.bones[i].rest());
+                match fk.bones[i].parent {
+                    Some(p) => i = p,
+                    None => return position,
+                }
+            }
+        };
+        //The rotation taking each bone's rest pose onto its joint's
+        let rest_offsets: Vec<Quat> = (0..bvh.joints.len())
+            .map(|i| {
+                let bone = match mapped[i] {
+                    Some(bone) => bone,
+                    None => return Quat::IDENTITY,
+                };
+                let children: Vec<usize> = (0..bvh.joints.len())
+                    .filter(|&c| mapped[c].is_some() && parents[c] == Some(i))
+                    .collect();
+                let direction = |c: Option<&usize>| match c {
+                    Some(&c) => (
+                        math::sub(bone_rest(mapped[c].unwrap()), bone_rest(bone)),
+                        math::sub(rig_rest[c], rig_rest[i]),
+                    ),
+                    None => ([0.; 3], [0.; 3]),
+                };
+                let (bone_dir, rig_dir) = direction(children.first());
+                let (bone_up, rig_up) = direction(children.get(1));
+                if math::length(bone_dir) < 1e-6 || math::length(rig_dir) < 1e-6 {
+                    return Quat::IDENTITY;
+                }
+                align((bone_dir, bone_up), (rig_dir, rig_up))
+            })
+            .collect();
+        let worlds: Vec<Vec<Transform>> = (0..bvh.frames.len()).map(|f| bvh.world(f)).collect();
+
+        let mut anims = BTreeMap::new();
+        for (i, bone) in mapped.iter().enumerate() {
+            let bone = match bone {
+                Some(bone) => &fk.bones[*bone],
+                None => continue,
+            };
+            let parent = parents[i];
+            let local: Vec<Transform> = worlds
+                .iter()
+                .map(|world| {
+                    let rotation = |j: usize| world[j].rotation * rest_offsets[j];
+                    match parent {
+                        Some(p) => Transform::new(
+                            world[i].translation,
+                            rotation(p).conjugate() * rotation(i),
+                        ),
+                        None => Transform::new(world[i].translation, rotation(i)),
+                    }
+                })
+                .collect();
+            let rotations: Vec<Quat> = local.iter().map(|t| t.rotation).collect();
+            let rotation = fit(0, &to_euler(&rotations), tolerance);
+            let anim = match bone.mode {
+                BoneType::Type3 | BoneType::Position => {
+                    //Only joints without a mapped parent bring their own position along
+                    let positions: Vec<Vector3> = match parent {
+                        None => local
+                            .iter()
+                            .map(|t| math::scale(t.translation, scale))
+                            .collect(),
+                        Some(_) => vec![bone.rest()],
+                    };
+                    let position = fit(0, &positions, tolerance);
+                    match bone.mode {
+                        BoneType::Position => BoneAnim::Position(position),
+                        _ => BoneAnim::PositionRotation { position, rotation },
+                    }
+                }
+                _ => BoneAnim::Rotation(rotation),
+            };
+            anims.insert(Bone(Cow::Owned(bone.name.to_string())), Some(anim));
+        }
+
+        let mut mot = Motion {
+            frames: bvh.frames.len() as u16,
+            anims,
+        };
+        if (1. / bvh.frame_time - fps).abs() > 1e-3 {
+            let map = TempoMap::frame_rate(1. / bvh.frame_time, fps)
+                .map_err(|_| BvhError::BadFrameRate)?;
+            let last = map.map(bvh.frames.len().saturating_sub(1) as f32).round();
+            if last >= u16::MAX as f32 {
+                return Err(BvhError::TooLong(last as usize + 1));
+            }
+            mot.retime(&map, tolerance);
+        }
+        Ok(mot.to_ik(skeleton, tolerance))
+    }
+
+    ///Exports the motion on `skeleton` as BVH, renaming bones through `map` and multiplying
+    ///positions by `scale`.
+    ///
+    ///IK bones are baked with [`Motion::bake_ik`] first. `gblctr` and `kg_ya_ex` are merged into
+    ///a single root joint, and rotations use the game's order, `Zrotation Yrotation Xrotation`.
+    pub fn to_bvh(
+        &self,
+        skeleton: &Skeleton,
+        map: &BoneMap,
+        fps: f32,
+        scale: f32,
+        tolerance: f32,
+    ) -> Bvh {
+        use BvhChannel::*;
+        let fk = skeleton.without_ik();
+        let mot = self.bake_ik(skeleton, tolerance);
+        let rotation = vec![Zrotation, Yrotation, Xrotation];
+        let all = vec![
+            Xposition, Yposition, Zposition, Zrotation, Yrotation, Xrotation,
+        ];
+
+        let mut joints = vec![BvhJoint {
+            name: map.get("gblctr").to_string(),
+            parent: None,
+            offset: [0.; 3],
+            channels: all.clone(),
+            end_site: None,
+        }];
+        //BVH wants parents before children
+        let mut order = vec![];
+        let mut index = vec![0; fk.bones.len()];
+        let mut stack: Vec<usize> = (0..fk.bones.len())
+            .filter(|&i| fk.bones[i].parent.is_none())
+            .rev()
+            .collect();
+        while let Some(i) = stack.pop() {
+            let bone = &fk.bones[i];
+            let moves = matches!(bone.mode, BoneType::Position | BoneType::Type3);
+            index[i] = joints.len();
+            joints.push(BvhJoint {
+                name: map.get(&bone.name).to_string(),
+                parent: Some(bone.parent.map_or(0, |p| index[p])),
+                offset: math::scale(bone.rest(), scale),
+                channels: if moves { all.clone() } else { rotation.clone() },
+                end_site: None,
+            });
+            order.push(i);
+            let mut children: Vec<usize> = fk.children(i).collect();
+            if children.is_empty() {
+                joints[index[i]].end_site = Some([0.; 3]);
+            }
+            children.reverse();
+            stack.extend(children);
+        }
+
+        let frames = (0..mot.frames.max(1))
+            .map(|f| {
+                let f = f as f32;
+                let root = mot.root_transform(f);
+                let [z, y, x] = {
+                    let [x, y, z] = root.rotation.to_euler();
+                    [z.to_degrees(), y.to_degrees(), x.to_degrees()]
+                };
+                let mut values = math::scale(root.translation, scale).to_vec();
+                values.extend_from_slice(&[z, y, x]);
+                for &i in &order {
+                    let bone = &fk.bones[i];
+                    let anim = mot.anim(&bone.name);
+                    if joints[index[i]].channels.len() == 6 {
+                        let position = match anim {
+                            Some(BoneAnim::Position(p))
+                            | Some(BoneAnim::PositionRotation { position: p, .. }) => sample(p, f),
+                            _ => bone.rest(),
+                        };
+                        values.extend_from_slice(&math::scale(position, scale));
+                    }
+                    let [x, y, z] = match anim {
+                        Some(BoneAnim::Rotation(r))
+                        | Some(BoneAnim::PositionRotation { rotation: r, .. }) => {
+                            euler_degrees(r, f)
+                        }
+                        _ => [0.; 3],
+                    };
+                    values.extend_from_slice(&[z, y, x]);
+                }
+                values
+            })
+            .collect();
+        Bvh {
+            joints,
+            frame_time: 1. / fps,
+            frames,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::channel::ChannelPath;
+
+    const BVH: &str = "HIERARCHY
+ROOT Hips
+{
+	OFFSET 0 0 0
+	CHANNELS 6 Xposition Yposition Zposition Zrotation Xrotation Yrotation
+	JOINT Spine
+	{
+		OFFSET 0 10 0
+		CHANNELS 3 Zrotation Xrotation Yrotation
+		JOINT Neck
+		{
+			OFFSET 0 40 0
+			CHANNELS 3 Zrotation Xrotation Yrotation
+			End Site
+			{
+				OFFSET 0 10 0
+			}
+		}
+	}
+}
+MOTION
+Frames: 2
+Frame Time: 0.0333333
+0 100 0 0 0 0 0 10 0 0 0 0
+1 100 0 0 0 0 0 20 0 0 30 0
+";
+
+    fn skeleton() -> Skeleton<'static> {
+        Skeleton::test(&[
+            ("n_hara_cp", BoneType::Type3, None, &[[0., 1., 0.]]),
+            ("kl_kubi", BoneType::Rotation, Some(0), &[[0., 0.5, 0.]]),
+        ])
+    }
+
+    #[test]
+    fn parse() -> anyhow::Result<()> {
+        let bvh = Bvh::parse(BVH)?;
+        assert_eq!(bvh.joints.len(), 3);
+        assert_eq!(bvh.joints[2].parent, Some(1));
+        assert_eq!(bvh.joints[2].end_site, Some([0., 10., 0.]));
+        assert_eq!(bvh.frames[1][0], 1.);
+        assert_eq!(Bvh::parse(&bvh.to_string())?, bvh);
+        Ok(())
+    }
+
+    #[test]
+    fn spine_folds_into_neck() -> anyhow::Result<()> {
+        let bvh = Bvh::parse(BVH)?;
+        //Spine has no counterpart, so its bend ends up on the neck
+        let mot = Motion::from_bvh(&bvh, &BoneMap::mocap(), &skeleton(), 30., 0.01, 1e-5)?;
+        let value = |path: &str, frame: f32| {
+            let path = ChannelPath::parse(path).unwrap();
+            mot.get(&path).unwrap().evaluate(frame).unwrap()
+        };
+        assert!((value("n_hara_cp.position.y", 0.) - 1.).abs() < 1e-5);
+        assert!((value("n_hara_cp.position.x", 1.) - 0.01).abs() < 1e-5);
+        assert!((value("kl_kubi.rotation.x", 0.) - 10f32.to_radians()).abs() < 1e-4);
+        assert!((value("kl_kubi.rotation.x", 1.) - 50f32.to_radians()).abs() < 1e-4);
+        Ok(())
+    }
+
+    #[test]
+    fn export_roundtrip() -> anyhow::Result<()> {
+        let v = |x, y, z| (FrameData::Pose(x), FrameData::Pose(y), FrameData::Pose(z));
+        let mut anims = BTreeMap::new();
+        anims.insert(
+            Bone("n_hara_cp".into()),
+            Some(BoneAnim::PositionRotation {
+                position: v(0.5, 1., 0.),
+                rotation: v(0.1, 0.2, 0.3),
+            }),
+        );
+        anims.insert(
+            Bone("kl_kubi".into()),
+            Some(BoneAnim::Rotation(v(-0.4, 0.5, 0.6))),
+        );
+        let mot = Motion { frames: 2, anims };
+        let bvh = mot.to_bvh(&skeleton(), &BoneMap::new(), 60., 100., 1e-5);
+        let bvh = Bvh::parse(&bvh.to_string())?;
+        let back = Motion::from_bvh(&bvh, &BoneMap::new(), &skeleton(), 60., 0.01, 1e-5)?;
+        for path in &[
+            "n_hara_cp.position.x",
+            "n_hara_cp.rotation.z",
+            "kl_kubi.rotation.x",
+            "kl_kubi.rotation.y",
+        ] {
+            let path = ChannelPath::parse(path)?;
+            let a = mot.get(&path).unwrap().evaluate(1.).unwrap();
+            let b = back.get(&path).unwrap().evaluate(1.).unwrap();
+            assert!((a - b).abs() < 1e-4, "{}: {} != {}", path, a, b);
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn t_pose_retargets_through_rest() -> anyhow::Result<()> {
+        let bvh = Bvh::parse(
+            "HIERARCHY
+ROOT mixamorig:Hips
+{
+	OFFSET 0 100 0
+	CHANNELS 6 Xposition Yposition Zposition Zrotation Xrotation Yrotation
+	JOINT mixamorig:LeftArm
+	{
+		OFFSET 20 40 0
+		CHANNELS 3 Zrotation Xrotation Yrotation
+		JOINT mixamorig:LeftForeArm
+		{
+			OFFSET 30 0 0
+			CHANNELS 3 Zrotation Xrotation Yrotation
+			End Site
+			{
+				OFFSET 25 0 0
+			}
+		}
+	}
+}
+MOTION
+Frames: 2
+Frame Time: 0.0166667
+0 100 0 0 0 0 0 0 0 0 0 0
+0 100 0 0 0 0 90 0 0 0 0 0
+",
+        )?;
+        //The Diva arm hangs down and forwards at rest
+        let skeleton = Skeleton::test(&[
+            ("n_hara_cp", BoneType::Type3, None, &[[0., 1., 0.]]),
+            (
+                "j_kata_l_wj_cu",
+                BoneType::Rotation,
+                Some(0),
+                &[[0.2, 0.4, 0.]],
+            ),
+            (
+                "j_ude_l_wj",
+                BoneType::Rotation,
+                Some(1),
+                &[[0.1, -0.2, 0.1]],
+            ),
+            ("j_te_l_wj", BoneType::Rotation, Some(2), &[[0., -0.25, 0.]]),
+        ]);
+        let mot = Motion::from_bvh(&bvh, &BoneMap::mocap(), &skeleton, 60., 0.01, 1e-5)?;
+        let direction = |frame: f32| {
+            let pose = mot.world_pose(&skeleton, frame);
+            let shoulder = pose.get("j_kata_l_wj_cu").unwrap().translation;
+            let elbow = pose.get("j_ude_l_wj").unwrap().translation;
+            math::normalize(math::sub(elbow, shoulder))
+        };
+        //Held out to the side like the rig's rest pose, then raised straight up
+        assert!(math::length(math::sub(direction(0.), [1., 0., 0.])) < 1e-3);
+        assert!(math::length(math::sub(direction(1.), [0., 1., 0.])) < 1e-3);
+        Ok(())
+    }
+
+    #[test]
+    fn mocap_names() {
+        let map = BoneMap::mocap();
+        assert_eq!(map.inverse().get("n_hara_cp"), "Hips");
+        assert_eq!(map.inverse().get("j_kata_l_wj_cu"), "LeftArm");
+    }
+
+    #[test]
+    fn too_long() {
+        let bvh = Bvh {
+            joints: vec![],
+            frame_time: 1. / 60.,
+            frames: vec![vec![]; 70000],
+        };
+        let mot = Motion::from_bvh(&bvh, &BoneMap::new(), &skeleton(), 60., 1., 1e-5);
+        assert_eq!(mot, Err(BvhError::TooLong(70000)));
+        let bvh = Bvh {
+            frames: vec![vec![]; 40000],
+            ..bvh
+        };
+        let mot = Motion::from_bvh(&bvh, &BoneMap::new(), &skeleton(), 120., 1., 1e-5);
+        assert_eq!(mot, Err(BvhError::TooLong(79999)));
+    }
+
+    #[test]
+    fn bad_frame_time() {
+        for &frame_time in &[0., -1. / 60., f32::NAN, f32::INFINITY] {
+            let bvh = Bvh {
+                joints: vec![],
+                frame_time,
+                frames: vec![vec![]; 2],
+            };
+            let mot = Motion::from_bvh(&bvh, &BoneMap::new(), &skeleton(), 60., 1., 1e-5);
+            assert_eq!(mot, Err(BvhError::BadFrameTime));
+        }
+    }
+}
@@ -0,0 +1,361 @@
+use super::*;
+
+use core::fmt;
+use core::str::FromStr;
+
+#[derive(Clone, PartialEq, Eq, PartialOrd, Ord, Debug, Error)]
+pub enum ChannelError {
+    #[error("Channel path `{0}` is not of the form `bone.component.axis`")]
+    Malformed(String),
+    #[error("Unknown component `{0}`")]
+    UnknownComponent(String),
+    #[error("Unknown axis `{0}`")]
+    UnknownAxis(String),
+    #[error("Motion has no channel `{0}`")]
+    NotFound(String),
+}
+
+#[derive(Copy, Clone, PartialEq, Eq, PartialOrd, Ord, Hash, Debug)]
+pub enum Component {
+    Position,
+    Rotation,
+    Target,
+    ///The nth vector of a Type 1 bone
+    Unk(u8),
+}
+
+#[derive(Copy, Clone, PartialEq, Eq, PartialOrd, Ord, Hash, Debug)]
+pub enum Axis {
+    X,
+    Y,
+    Z,
+}
+
+///Addresses a single animated curve of a [`Motion`], e.g. `kl_te_l_wj.rotation.x`
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct ChannelPath<'a> {
+    pub bone: Bone<'a>,
+    pub component: Component,
+    pub axis: Axis,
+}
+
+impl Axis {
+    pub const ALL: [Axis; 3] = [Axis::X, Axis::Y, Axis::Z];
+
+    pub fn get(self, (x, y, z): &Vec3) -> &FrameData {
+        match self {
+            Axis::X => x,
+            Axis::Y => y,
+            Axis::Z => z,
+        }
+    }
+
+    pub fn get_mut(self, (x, y, z): &mut Vec3) -> &mut FrameData {
+        match self {
+            Axis::X => x,
+            Axis::Y => y,
+            Axis::Z => z,
+        }
+    }
+}
+
+impl BoneAnim {
+    ///Returns the components of this animation in the order they are stored in a [`RawMotion`]
+    pub fn components(&self) -> Vec<(Component, &Vec3)> {
+        use Component::*;
+        match self {
+            BoneAnim::Rotation(v) => vec![(Rotation, v)],
+            BoneAnim::Unk(u, v) => vec![(Unk(0), u), (Unk(1), v)],
+            BoneAnim::Position(v) => vec![(Position, v)],
+            BoneAnim::PositionRotation { position, rotation } => {
+                vec![(Position, position), (Rotation, rotation)]
+            }
+            BoneAnim::RotationIk { target, rotation } => vec![(Target, target), (Rotation, rotation)],
+            BoneAnim::ArmIk { target, rotation } => vec![(Target, target), (Rotation, rotation)],
+            BoneAnim::LegIk { position, target } => vec![(Target, target), (Position, position)],
+        }
+    }
+
+    pub fn components_mut(&mut self) -> Vec<(Component, &mut Vec3)> {
+        use Component::*;
+        match self {
+            BoneAnim::Rotation(v) => vec![(Rotation, v)],
+            BoneAnim::Unk(u, v) => vec![(Unk(0), u), (Unk(1), v)],
+            BoneAnim::Position(v) => vec![(Position, v)],
+            BoneAnim::PositionRotation { position, rotation } => {
+                vec![(Position, position), (Rotation, rotation)]
+            }
+            BoneAnim::RotationIk { target, rotation } => vec![(Target, target), (Rotation, rotation)],
+            BoneAnim::ArmIk { target, rotation } => vec![(Target, target), (Rotation, rotation)],
+            BoneAnim::LegIk { position, target } => vec![(Target, target), (Position, position)],
+        }
+    }
+
+    pub fn component(&self, component: Component) -> Option<&Vec3> {
+        self.components()
+            .into_iter()
+            .find(|(c, _)| *c == component)
+            .map(|(_, v)| v)
+    }
+
+    pub fn component_mut(&mut self, component: Component) -> Option<&mut Vec3> {
+        self.components_mut()
+            .into_iter()
+            .find(|(c, _)| *c == component)
+            .map(|(_, v)| v)
+    }
+
+    ///Builds an animation of the same type with every channel passed through `f`
+    pub fn map<F>(&self, mut f: F) -> Self
+    where
+        F: FnMut(Component, Axis, &FrameData) -> FrameData,
+    {
+        let mut anim = self.clone();
+        for (component, vec) in anim.components_mut() {
+            for &axis in Axis::ALL.iter() {
+                let data = axis.get_mut(vec);
+                *data = f(component, axis, data);
+            }
+        }
+        anim
+    }
+
+    ///Like [`BoneAnim::map`] over pairs of channels, returns `None` if the types differ
+    pub fn zip_with<F>(&self, other: &BoneAnim, mut f: F) -> Option<Self>
+    where
+        F: FnMut(Component, Axis, &FrameData, &FrameData) -> FrameData,
+    {
+        if core::mem::discriminant(self) != core::mem::discriminant(other) {
+            return None;
+        }
+        let others = other.components();
+        Some(self.map(|component, axis, data| {
+            //Same variant, so the component is always present
+            let (_, vec) = others.iter().find(|(c, _)| *c == component).unwrap();
+            f(component, axis, data, axis.get(vec))
+        }))
+    }
+}
+
+impl<'a> Motion<'a> {
+    ///Iterates over every channel in the order they are stored in a [`RawMotion`]
+    pub fn channels(&self) -> impl Iterator<Item = (ChannelPath<'_>, &FrameData)> {
+        self.anims
+            .iter()
+            .filter_map(|(bone, anim)| anim.as_ref().map(|a| (bone, a)))
+            .flat_map(|(bone, anim)| {
+                anim.components().into_iter().flat_map(move |(component, vec)| {
+                    Axis::ALL.iter().map(move |&axis| {
+                        let path = ChannelPath {
+                            bone: Bone(Cow::Borrowed(&bone[..])),
+                            component,
+                            axis,
+                        };
+                        (path, axis.get(vec))
+                    })
+                })
+            })
+    }
+
+    pub fn channels_mut(&mut self) -> impl Iterator<Item = (ChannelPath<'_>, &mut FrameData)> {
+        //Collected as the map's iterator can't outlive the borrow of `'a`
+        let channels: Vec<_> = self
+            .anims
+            .iter_mut()
+            .filter_map(|(bone, anim)| anim.as_mut().map(|a| (bone, a)))
+            .flat_map(|(bone, anim)| {
+                anim.components_mut()
+                    .into_iter()
+                    .flat_map(move |(component, (x, y, z))| {
+                        vec![(Axis::X, x), (Axis::Y, y), (Axis::Z, z)]
+                            .into_iter()
+                            .map(move |(axis, data)| {
+                                let path = ChannelPath {
+                                    bone: Bone(Cow::Borrowed(&bone[..])),
+                                    component,
+                                    axis,
+                                };
+                                (path, data)
+                            })
+                    })
+            })
+            .collect();
+        channels.into_iter()
+    }
+
+    pub fn get(&self, path: &ChannelPath) -> Option<&FrameData> {
+        let anim = self.anim(&path.bone)?;
+        anim.component(path.component).map(|v| path.axis.get(v))
+    }
+
+    pub fn get_mut(&mut self, path: &ChannelPath) -> Option<&mut FrameData> {
+        let anim = self.anim_mut(&path.bone)?;
+        anim.component_mut(path.component).map(|v| path.axis.get_mut(v))
+    }
+
+    pub fn anim(&self, bone: &str) -> Option<&BoneAnim> {
+        self.anims
+            .iter()
+            .find(|(b, _)| &b[..] == bone)
+            .and_then(|(_, a)| a.as_ref())
+    }
+
+    pub fn anim_mut(&mut self, bone: &str) -> Option<&mut BoneAnim> {
+        self.anims
+            .iter_mut()
+            .find(|(b, _)| &b[..] == bone)
+            .and_then(|(_, a)| a.as_mut())
+    }
+
+    ///Replaces the data of an existing channel, returning the old data
+    pub fn set(&mut self, path: &ChannelPath, data: FrameData) -> Result<FrameData, ChannelError> {
+        let old = self
+            .get_mut(path)
+            .ok_or_else(|| ChannelError::NotFound(path.to_string()))?;
+        Ok(core::mem::replace(old, data))
+    }
+}
+
+impl<'a> ChannelPath<'a> {
+    pub fn new(bone: impl Into<Cow<'a, str>>, component: Component, axis: Axis) -> Self {
+        Self {
+            bone: Bone(bone.into()),
+            component,
+            axis,
+        }
+    }
+
+    ///Parses a path without copying the bone name
+    pub fn parse(s: &'a str) -> Result<Self, ChannelError> {
+        let mut parts = s.rsplitn(3, '.');
+        let (axis, component, bone) = match (parts.next(), parts.next(), parts.next()) {
+            (Some(a), Some(c), Some(b)) if !b.is_empty() => (a, c, b),
+            _ => return Err(ChannelError::Malformed(s.to_string())),
+        };
+        Ok(Self {
+            bone: Bone(Cow::Borrowed(bone)),
+            component: component.parse()?,
+            axis: axis.parse()?,
+        })
+    }
+
+    pub fn into_owned(self) -> ChannelPath<'static> {
+        ChannelPath {
+            bone: Bone(Cow::Owned(self.bone.0.into_owned())),
+            component: self.component,
+            axis: self.axis,
+        }
+    }
+}
+
+impl FromStr for ChannelPath<'static> {
+    type Err = ChannelError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        ChannelPath::parse(s).map(ChannelPath::into_owned)
+    }
+}
+
+impl FromStr for Component {
+    type Err = ChannelError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "position" => Ok(Component::Position),
+            "rotation" => Ok(Component::Rotation),
+            "target" => Ok(Component::Target),
+            "unk" | "unk0" => Ok(Component::Unk(0)),
+            "unk1" => Ok(Component::Unk(1)),
+            _ => Err(ChannelError::UnknownComponent(s.to_string())),
+        }
+    }
+}
+
+impl FromStr for Axis {
+    type Err = ChannelError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "x" => Ok(Axis::X),
+            "y" => Ok(Axis::Y),
+            "z" => Ok(Axis::Z),
+            _ => Err(ChannelError::UnknownAxis(s.to_string())),
+        }
+    }
+}
+
+impl fmt::Display for Component {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Component::Position => write!(f, "position"),
+            Component::Rotation => write!(f, "rotation"),
+            Component::Target => write!(f, "target"),
+            Component::Unk(n) => write!(f, "unk{}", n),
+        }
+    }
+}
+
+impl fmt::Display for Axis {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Axis::X => write!(f, "x"),
+            Axis::Y => write!(f, "y"),
+            Axis::Z => write!(f, "z"),
+        }
+    }
+}
+
+impl fmt::Display for ChannelPath<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}.{}.{}", &self.bone[..], self.component, self.axis)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn motion() -> Motion<'static> {
+        let mut anims = BTreeMap::new();
+        let rot = (FrameData::Pose(1.), FrameData::Pose(2.), FrameData::Pose(3.));
+        anims.insert(Bone("kl_te_l_wj".into()), Some(BoneAnim::Rotation(rot)));
+        anims.insert(Bone("n_hara_cp".into()), None);
+        Motion { frames: 10, anims }
+    }
+
+    #[test]
+    fn parse_path() -> anyhow::Result<()> {
+        let path: ChannelPath = "kl_te_l_wj.rotation.x".parse()?;
+        assert_eq!(path, ChannelPath::new("kl_te_l_wj", Component::Rotation, Axis::X));
+        assert_eq!(path.to_string(), "kl_te_l_wj.rotation.x");
+        assert_eq!(
+            ChannelPath::parse("rotation.x"),
+            Err(ChannelError::Malformed("rotation.x".into()))
+        );
+        assert_eq!(
+            ChannelPath::parse("a.scale.x"),
+            Err(ChannelError::UnknownComponent("scale".into()))
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn get_set() -> anyhow::Result<()> {
+        let mut mot = motion();
+        assert_eq!(mot.channels().count(), 3);
+        let path = ChannelPath::parse("kl_te_l_wj.rotation.y")?;
+        assert_eq!(mot.get(&path), Some(&FrameData::Pose(2.)));
+        let old = mot.set(&path, FrameData::Pose(5.))?;
+        assert_eq!(old, FrameData::Pose(2.));
+        assert_eq!(mot.get(&path), Some(&FrameData::Pose(5.)));
+
+        let missing = ChannelPath::parse("kl_te_l_wj.position.y")?;
+        assert!(mot.set(&missing, FrameData::None).is_err());
+
+        for (_, data) in mot.channels_mut() {
+            *data = FrameData::None;
+        }
+        assert!(mot.channels().all(|(_, d)| *d == FrameData::None));
+        Ok(())
+    }
+}
@@ -0,0 +1,261 @@
+use super::*;
+use crate::blend::{fit, sample};
+use crate::math::{self, Transform, Vector3};
+use crate::pose::WorldPose;
+use crate::skeleton::Skeleton;
+use core::ops::Range;
+
+#[derive(Copy, Clone, PartialEq, Eq, PartialOrd, Ord, Hash, Debug)]
+pub enum Foot {
+    Left,
+    Right,
+}
+
+impl Foot {
+    pub const ALL: [Foot; 2] = [Foot::Left, Foot::Right];
+
+    ///The leg IK bone, the ankle and the toe of this foot
+    pub fn bones(self) -> [&'static str; 3] {
+        match self {
+            Foot::Left => ["cl_momo_l", "kl_asi_l_wj_co", "kl_toe_l_wj"],
+            Foot::Right => ["cl_momo_r", "kl_asi_r_wj_co", "kl_toe_r_wj"],
+        }
+    }
+}
+
+///Thresholds for deciding when a foot is planted
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub struct ContactSettings {
+    ///How far above its lowest point the ankle or toe may be
+    pub height: f32,
+    ///How far the ankle may move over the floor in one frame
+    pub speed: f32,
+    ///Contacts shorter than this are ignored
+    pub min_frames: u16,
+    ///Frames over which a locked foot eases back into its original path
+    pub blend: u16,
+}
+
+impl Default for ContactSettings {
+    fn default() -> Self {
+        Self {
+            height: 0.03,
+            speed: 0.01,
+            min_frames: 3,
+            blend: 4,
+        }
+    }
+}
+
+///The transform an IK bone's target is relative to
+fn parent(pose: &WorldPose, skeleton: &Skeleton, leg: usize) -> Transform {
+    match skeleton.bones[leg].parent {
+        Some(p) => {
+            let name = &skeleton.bones[p].name[..];
+            pose.effector(name)
+                .or_else(|| pose.get(name))
+                .unwrap_or_default()
+        }
+        None => pose.root,
+    }
+}
+
+impl<'a> Motion<'a> {
+    ///Finds the frame ranges where `foot` is planted.
+    ///
+    ///The floor is taken to be the lowest the foot gets during the motion, so heels and stages
+    ///of any height work the same.
+    pub fn foot_contacts(
+        &self,
+        skeleton: &Skeleton,
+        foot: Foot,
+        settings: &ContactSettings,
+    ) -> Vec<Range<u16>> {
+        let [leg, ankle, toe] = foot.bones();
+        let frames = self.frames.max(1);
+        //Lowest point of the foot and position of the ankle on every frame
+        let samples: Vec<Option<(f32, Vector3)>> = (0..frames)
+            .map(|f| {
+                let pose = self.world_pose(skeleton, f as f32);
+                let ankle = pose.get(ankle).or_else(|| pose.effector(leg))?.translation;
+                let low = match pose.get(toe) {
+                    Some(toe) => ankle[1].min(toe.translation[1]),
+                    None => ankle[1],
+                };
+                Some((low, ankle))
+            })
+            .collect();
+        let floor = samples
+            .iter()
+            .flatten()
+            .map(|(low, _)| *low)
+            .fold(f32::INFINITY, f32::min);
+
+        let planted = |i: usize| {
+            let (low, ankle) = match samples[i] {
+                Some(x) => x,
+                None => return false,
+            };
+            let neighbour = |j: usize| samples.get(j).copied().flatten().map(|(_, x)| x);
+            let prev = i.checked_sub(1).and_then(neighbour).unwrap_or(ankle);
+            let next = neighbour(i + 1).unwrap_or(ankle);
+            let (a, b) = (prev, next);
+            let moved = math::length([b[0] - a[0], 0., b[2] - a[2]]) / 2.;
+            low - floor <= settings.height && moved <= settings.speed
+        };
+
+        let mut contacts = vec![];
+        let mut start = None;
+        for i in 0..=frames as usize {
+            match (start, i < frames as usize && planted(i)) {
+                (None, true) => start = Some(i as u16),
+                (Some(s), false) => {
+                    if i as u16 - s >= settings.min_frames {
+                        contacts.push(s..i as u16);
+                    }
+                    start = None;
+                }
+                _ => (),
+            }
+        }
+        contacts
+    }
+
+    ///Removes foot sliding by pinning the `LegIk` targets in place during each contact found
+    ///by [`Motion::foot_contacts`].
+    ///
+    ///Each foot is pinned to where it is on average during the contact and eases back into its
+    ///own path over `settings.blend` frames on either side.
+    pub fn lock_feet(
+        &self,
+        skeleton: &Skeleton,
+        settings: &ContactSettings,
+        tolerance: f32,
+    ) -> Self {
+        let frames = self.frames.max(1);
+        let poses: Vec<WorldPose> = (0..frames)
+            .map(|f| self.world_pose(skeleton, f as f32))
+            .collect();
+        let mut mot = self.clone();
+        for &foot in Foot::ALL.iter() {
+            let leg_name = foot.bones()[0];
+            let leg = match skeleton.find(leg_name) {
+                Some(leg) => leg,
+                None => continue,
+            };
+            let target = match self.anim(leg_name) {
+                Some(BoneAnim::LegIk { target, .. }) => target,
+                _ => continue,
+            };
+            let contacts = self.foot_contacts(skeleton, foot, settings);
+            if contacts.is_empty() {
+                continue;
+            }
+            let parents: Vec<Transform> = poses.iter().map(|p| parent(p, skeleton, leg)).collect();
+            //Where the target is in world space on every frame
+            let original: Vec<Vector3> = parents
+                .iter()
+                .enumerate()
+                .map(|(f, p)| p.apply(sample(target, f as f32)))
+                .collect();
+            let mut targets = original.clone();
+            for contact in &contacts {
+                let range = contact.start as usize..contact.end as usize;
+                let count = range.len() as f32;
+                let pin = original[range.clone()]
+                    .iter()
+                    .fold([0.; 3], |a, &b| math::add(a, math::scale(b, 1. / count)));
+                for f in range.clone() {
+                    targets[f] = pin;
+                }
+                //Ease in and out of the pinned position
+                let blend = settings.blend as usize;
+                for n in 1..=blend {
+                    let weight = 1. - n as f32 / (blend + 1) as f32;
+                    for f in [range.start.checked_sub(n), Some(range.end - 1 + n)]
+                        .iter()
+                        .flatten()
+                    {
+                        if *f < frames as usize
+                            && !contacts.iter().any(|c| c.contains(&(*f as u16)))
+                        {
+                            targets[*f] = math::lerp(targets[*f], pin, weight);
+                        }
+                    }
+                }
+            }
+            let local: Vec<Vector3> = targets
+                .iter()
+                .zip(&parents)
+                .map(|(&t, p)| p.inverse().apply(t))
+                .collect();
+            if let Some(BoneAnim::LegIk { target, .. }) = mot.anim_mut(leg_name) {
+                *target = fit(0, &local, tolerance);
+            }
+        }
+        mot
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use diva_db::bone::BoneType;
+
+    fn skeleton() -> Skeleton<'static> {
+        Skeleton::test(&[
+            ("n_hara_cp", BoneType::Type3, None, &[[0., 1., 0.]]),
+            (
+                "cl_momo_l",
+                BoneType::Type6,
+                Some(0),
+                &[[0.1, 0., 0.], [0., -0.5, 0.05], [0., -0.5, -0.05]],
+            ),
+        ])
+    }
+
+    #[test]
+    fn lock_stops_sliding() {
+        let p = |x| FrameData::Pose(x);
+        //The body sways while the foot slides a little on the floor, then steps
+        let samples: Vec<f32> = (0..20).map(|f| (f as f32 * 0.3).sin() * 0.05).collect();
+        let slide: Vec<f32> = (0..20).map(|f| f as f32 * 0.005 - samples[f]).collect();
+        let lift: Vec<f32> = (0..20).map(|f| if f < 10 { -0.95 } else { -0.8 }).collect();
+        let mut anims = BTreeMap::new();
+        anims.insert(
+            Bone("n_hara_cp".into()),
+            Some(BoneAnim::PositionRotation {
+                position: (p(0.), p(1.), FrameData::fit(0, &samples, 1e-5)),
+                rotation: (p(0.), p(0.), p(0.)),
+            }),
+        );
+        anims.insert(
+            Bone("cl_momo_l".into()),
+            Some(BoneAnim::LegIk {
+                position: (p(0.1), p(0.), p(0.)),
+                target: (
+                    p(0.1),
+                    FrameData::fit(0, &lift, 1e-5),
+                    FrameData::fit(0, &slide, 1e-5),
+                ),
+            }),
+        );
+        let mot = Motion { frames: 20, anims };
+        let settings = ContactSettings {
+            blend: 0,
+            ..Default::default()
+        };
+        assert_eq!(
+            mot.foot_contacts(&skeleton(), Foot::Left, &settings),
+            vec![0..10]
+        );
+
+        let locked = mot.lock_feet(&skeleton(), &settings, 1e-5);
+        let foot = |f: u16| {
+            let pose = locked.world_pose(&skeleton(), f as f32);
+            pose.effector("cl_momo_l").unwrap().translation
+        };
+        assert!(math::length(math::sub(foot(0), foot(9))) < 1e-3);
+        assert!(foot(15)[1] > foot(5)[1] + 0.1);
+    }
+}
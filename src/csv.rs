@@ -0,0 +1,592 @@
+//!Spreadsheet friendly dumps of motions.
+//!
+//!The wide form has a `frame` column followed by one column per channel, with a row for every
+//!frame holding the evaluated curves. The long form keeps the curves exactly, with a row
+//!without a frame starting each curve and one row per keyframe after it:
+//!
+//!```text
+//!channel,type,frame,value,tangent
+//!,motion,,120,
+//!n_hara_cp,position_rotation,,,
+//!n_hara_cp.position.x,none,,,
+//!n_hara_cp.position.y,pose,,1,
+//!n_hara_cp.position.z,catmull_rom,,,
+//!n_hara_cp.position.z,catmull_rom,0,0,
+//!n_hara_cp.position.z,catmull_rom,60,0.5,
+//!n_hara_cp.rotation.x,hermite,,,
+//!n_hara_cp.rotation.x,hermite,0,0.1,0
+//!```
+//!
+//!Raw motions use the set index as the channel, with `raw` and `bone` rows for the frame count
+//!and bone ids. Cells are split on the separator given, so both CSV and TSV work.
+use super::*;
+use crate::channel::{ChannelError, ChannelPath, Component};
+use crate::skeleton::Skeleton;
+use diva_db::bone::BoneType;
+
+use core::convert::TryFrom;
+use core::fmt::Write;
+use core::str::FromStr;
+
+#[derive(Clone, PartialEq, Debug, Error)]
+pub enum CsvError {
+    #[error("Missing header row")]
+    NoHeader,
+    #[error("Line {0}: expected {1}")]
+    Expected(usize, &'static str),
+    #[error("Line {0}: `{1}` is not a number")]
+    BadNumber(usize, String),
+    #[error("Line {0}: unknown type `{1}`")]
+    UnknownType(usize, String),
+    #[error("Line {0}: {1}")]
+    Channel(usize, #[source] ChannelError),
+    #[error("Line {0}: no bone `{1}` was declared before its channels")]
+    UndeclaredBone(usize, String),
+    #[error("No animation type has the channels given for `{0}`")]
+    NoAnimation(String),
+    #[error("{0} frames don't fit in a motion")]
+    TooLong(usize),
+}
+
+const LONG_HEADER: [&str; 5] = ["channel", "type", "frame", "value", "tangent"];
+
+///The cells of the non empty lines with their line numbers
+fn rows(text: &str, separator: char) -> impl Iterator<Item = (usize, Vec<&str>)> {
+    text.lines()
+        .enumerate()
+        .filter(|(_, line)| !line.trim().is_empty())
+        .map(move |(i, line)| {
+            let cells = line
+                .split(separator)
+                .map(|c| c.trim().trim_matches('"'))
+                .collect();
+            (i + 1, cells)
+        })
+}
+
+fn number<T: FromStr>(line: usize, cell: &str) -> Result<T, CsvError> {
+    cell.parse()
+        .map_err(|_| CsvError::BadNumber(line, cell.to_string()))
+}
+
+fn join(cells: &[String], separator: char) -> String {
+    let mut row = cells.join(&separator.to_string()[..]);
+    row.push('\n');
+    row
+}
+
+///Writes the rows of one curve in the long form
+fn write_curve(out: &mut String, channel: &str, data: &FrameData, separator: char) {
+    let s = separator;
+    match data {
+        FrameData::None => {
+            let _ = writeln!(out, "{}{}none{}{}{}", channel, s, s, s, s);
+        }
+        FrameData::Pose(x) => {
+            let _ = writeln!(out, "{}{}pose{}{}{}{}", channel, s, s, s, x, s);
+        }
+        FrameData::CatmulRom(keys) => {
+            let _ = writeln!(out, "{}{}catmull_rom{}{}{}", channel, s, s, s, s);
+            for key in keys {
+                let _ = writeln!(
+                    out,
+                    "{}{}catmull_rom{}{}{}{}{}",
+                    channel, s, s, key.frame, s, key.value, s
+                );
+            }
+        }
+        FrameData::Hermite(keys) => {
+            let _ = writeln!(out, "{}{}hermite{}{}{}{}", channel, s, s, s, s, s);
+            for key in keys {
+                let _ = writeln!(
+                    out,
+                    "{}{}hermite{}{}{}{}{}{}",
+                    channel, s, s, key.frame, s, key.value, s, key.interpolation
+                );
+            }
+        }
+    }
+}
+
+///Adds the row `cells` of type `ty` to `data`. Rows of a keyed type without a frame start a new
+///curve, the ones after them add its keys.
+fn read_curve(data: &mut FrameData, line: usize, ty: &str, cells: &[&str]) -> Result<(), CsvError> {
+    let cell = |i: usize| cells.get(i).copied().unwrap_or_default();
+    match ty {
+        "none" => *data = FrameData::None,
+        "pose" => *data = FrameData::Pose(number(line, cell(3))?),
+        "catmull_rom" if cell(2).is_empty() => *data = FrameData::CatmulRom(vec![]),
+        "hermite" if cell(2).is_empty() => *data = FrameData::Hermite(vec![]),
+        "catmull_rom" => {
+            let key = Keyframe {
+                frame: number(line, cell(2))?,
+                value: number(line, cell(3))?,
+                interpolation: (),
+            };
+            match data {
+                FrameData::CatmulRom(keys) => keys.push(key),
+                _ => {
+                    return Err(CsvError::Expected(
+                        line,
+                        "a `catmull_rom` row before its keys",
+                    ))
+                }
+            }
+        }
+        "hermite" => {
+            let key = Keyframe {
+                frame: number(line, cell(2))?,
+                value: number(line, cell(3))?,
+                interpolation: number(line, cell(4))?,
+            };
+            match data {
+                FrameData::Hermite(keys) => keys.push(key),
+                _ => return Err(CsvError::Expected(line, "a `hermite` row before its keys")),
+            }
+        }
+        _ => return Err(CsvError::UnknownType(line, ty.to_string())),
+    }
+    Ok(())
+}
+
+///Checks the header and returns the remaining rows
+fn long_rows(
+    text: &str,
+    separator: char,
+) -> Result<impl Iterator<Item = (usize, Vec<&str>)>, CsvError> {
+    let mut rows = rows(text, separator);
+    match rows.next() {
+        Some((_, header)) if header[..] == LONG_HEADER[..] => Ok(rows),
+        Some((i, _)) => Err(CsvError::Expected(
+            i,
+            "a `channel,type,frame,value,tangent` header",
+        )),
+        None => Err(CsvError::NoHeader),
+    }
+}
+
+///The animation type of a skeleton bone
+fn kind(mode: BoneType) -> &'static str {
+    match mode {
+        BoneType::Rotation => "rotation",
+        BoneType::Type1 => "unk",
+        BoneType::Position => "position",
+        BoneType::Type3 => "position_rotation",
+        BoneType::Type4 => "rotation_ik",
+        BoneType::Type5 => "arm_ik",
+        BoneType::Type6 => "leg_ik",
+    }
+}
+
+///The animation that has exactly `components`, if only one does
+fn from_components(components: &[Component]) -> Option<BoneAnim> {
+    use Component::*;
+    let has = |c| components.contains(&c);
+    let kind = match (has(Position), has(Rotation), has(Target), has(Unk(0))) {
+        (false, true, false, false) => "rotation",
+        (true, false, false, false) => "position",
+        (true, true, false, false) => "position_rotation",
+        (true, false, true, false) => "leg_ik",
+        (false, false, false, true) => "unk",
+        _ => return None,
+    };
+    BoneAnim::empty(kind)
+}
+
+///The cells of each column of a wide dump, empty ones being `None`
+type Columns = Vec<Vec<Option<f32>>>;
+
+///Reads the `frame` column and the columns after it of a wide dump along with the number of
+///frames, checking that there is a row for every frame
+fn wide_columns(text: &str, separator: char) -> Result<(Vec<&str>, Columns, u16), CsvError> {
+    let mut rows = rows(text, separator);
+    let (_, header) = rows.next().ok_or(CsvError::NoHeader)?;
+    if header.first() != Some(&"frame") {
+        return Err(CsvError::Expected(1, "a `frame` column first"));
+    }
+    let mut columns: Columns = vec![vec![]; header.len() - 1];
+    let mut frames = 0;
+    for (frame, (i, cells)) in rows.enumerate() {
+        frames += 1;
+        let first = cells.first().copied().unwrap_or_default();
+        if number::<usize>(i, first)? != frame {
+            return Err(CsvError::Expected(i, "frames counting up from 0"));
+        }
+        for (n, column) in columns.iter_mut().enumerate() {
+            let cell = cells.get(n + 1).copied().unwrap_or_default();
+            column.push(match cell {
+                "" => None,
+                _ => Some(number(i, cell)?),
+            });
+        }
+    }
+    let frames = u16::try_from(frames).map_err(|_| CsvError::TooLong(frames))?;
+    Ok((header[1..].to_vec(), columns, frames))
+}
+
+///Fits a column of a wide dump, with empty cells holding the value above them
+fn fit_column(column: &[Option<f32>], tolerance: f32) -> FrameData {
+    match column.iter().flatten().next() {
+        None => FrameData::None,
+        Some(&first) => {
+            let mut last = first;
+            let samples: Vec<f32> = column
+                .iter()
+                .map(|x| {
+                    last = x.unwrap_or(last);
+                    last
+                })
+                .collect();
+            FrameData::fit(0, &samples, tolerance)
+        }
+    }
+}
+
+impl<'a> Motion<'a> {
+    ///One column per channel and one row per frame, with cells left empty where a channel has no
+    ///curve
+    pub fn to_csv_wide(&self, separator: char) -> String {
+        let channels: Vec<_> = self.channels().collect();
+        let mut header = vec!["frame".to_string()];
+        header.extend(channels.iter().map(|(path, _)| path.to_string()));
+        let mut out = join(&header, separator);
+        for f in 0..self.frames {
+            let mut row = vec![f.to_string()];
+            row.extend(channels.iter().map(|(_, data)| {
+                data.evaluate(f as f32)
+                    .map(|x| x.to_string())
+                    .unwrap_or_default()
+            }));
+            out.push_str(&join(&row, separator));
+        }
+        out
+    }
+
+    ///Reads a wide dump, fitting every column to `tolerance`.
+    ///
+    ///Animation types come from the bones of `skeleton`. Bones it doesn't have, like `gblctr`,
+    ///are told apart by their components. Empty cells hold the value above them, and columns that
+    ///are entirely empty have no curve.
+    pub fn from_csv_wide(
+        text: &str,
+        separator: char,
+        skeleton: &Skeleton,
+        tolerance: f32,
+    ) -> Result<Motion<'static>, CsvError> {
+        let (header, columns, frames) = wide_columns(text, separator)?;
+        let paths = header
+            .iter()
+            .map(|c| {
+                c.parse::<ChannelPath>()
+                    .map_err(|e| CsvError::Channel(1, e))
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+
+        let mut anims = BTreeMap::new();
+        let mut bones: Vec<&str> = paths.iter().map(|p| &p.bone[..]).collect();
+        bones.dedup();
+        for bone in bones {
+            let anim = match skeleton.get(bone) {
+                Some(b) => BoneAnim::empty(kind(b.mode)),
+                None => {
+                    let components: Vec<Component> = paths
+                        .iter()
+                        .filter(|p| &p.bone[..] == bone)
+                        .map(|p| p.component)
+                        .collect();
+                    from_components(&components)
+                }
+            };
+            let anim = anim.ok_or_else(|| CsvError::NoAnimation(bone.to_string()))?;
+            anims.insert(Bone(Cow::Owned(bone.to_string())), Some(anim));
+        }
+        let mut mot = Motion { frames, anims };
+        for (path, column) in paths.iter().zip(&columns) {
+            let data = mot
+                .get_mut(path)
+                .ok_or_else(|| CsvError::NoAnimation(path.bone[..].to_string()))?;
+            *data = fit_column(column, tolerance);
+        }
+        Ok(mot)
+    }
+
+    ///One row per keyframe, reading back to the same motion
+    pub fn to_csv_long(&self, separator: char) -> String {
+        let s = separator;
+        let mut out = join(
+            &LONG_HEADER
+                .iter()
+                .map(|x| x.to_string())
+                .collect::<Vec<_>>(),
+            s,
+        );
+        let _ = writeln!(out, "{}motion{}{}{}{}", s, s, s, self.frames, s);
+        for (bone, anim) in &self.anims {
+            let kind = anim.as_ref().map_or("none", BoneAnim::kind);
+            let _ = writeln!(out, "{}{}{}{}{}{}{}", &bone[..], s, kind, s, s, s, s);
+            if let Some(anim) = anim {
+                for (component, vec) in anim.components() {
+                    for (axis, data) in ["x", "y", "z"].iter().zip(&[&vec.0, &vec.1, &vec.2]) {
+                        let channel = format!("{}.{}.{}", &bone[..], component, axis);
+                        write_curve(&mut out, &channel, data, s);
+                    }
+                }
+            }
+        }
+        out
+    }
+
+    pub fn from_csv_long(text: &str, separator: char) -> Result<Motion<'static>, CsvError> {
+        let mut mot = Motion::default();
+        for (i, cells) in long_rows(text, separator)? {
+            let (channel, ty) = match &cells[..] {
+                [channel, ty, ..] => (*channel, *ty),
+                _ => return Err(CsvError::Expected(i, "a channel and a type")),
+            };
+            if ty == "motion" {
+                mot.frames = number(i, cells.get(3).copied().unwrap_or_default())?;
+                continue;
+            }
+            if !channel.contains('.') {
+                let anim = match ty {
+                    "none" => None,
+                    _ => Some(
+                        BoneAnim::empty(ty)
+                            .ok_or_else(|| CsvError::UnknownType(i, ty.to_string()))?,
+                    ),
+                };
+                mot.anims
+                    .insert(Bone(Cow::Owned(channel.to_string())), anim);
+                continue;
+            }
+            let path = ChannelPath::parse(channel).map_err(|e| CsvError::Channel(i, e))?;
+            let data = mot
+                .get_mut(&path)
+                .ok_or_else(|| CsvError::UndeclaredBone(i, path.bone[..].to_string()))?;
+            read_curve(data, i, ty, &cells)?;
+        }
+        Ok(mot)
+    }
+}
+
+impl RawMotion {
+    ///One column per set and one row per frame
+    pub fn to_csv_wide(&self, separator: char) -> String {
+        let mut header = vec!["frame".to_string()];
+        header.extend((0..self.sets.len()).map(|i| i.to_string()));
+        let mut out = join(&header, separator);
+        for f in 0..self.frames {
+            let mut row = vec![f.to_string()];
+            row.extend(self.sets.iter().map(|data| {
+                data.evaluate(f as f32)
+                    .map(|x| x.to_string())
+                    .unwrap_or_default()
+            }));
+            out.push_str(&join(&row, separator));
+        }
+        out
+    }
+
+    ///Reads a wide dump, fitting every set to `tolerance`. The dump doesn't hold the bone ids,
+    ///so they are given as `bones`, in the same order as the motion the dump was made from.
+    pub fn from_csv_wide(
+        text: &str,
+        separator: char,
+        bones: Vec<u16>,
+        tolerance: f32,
+    ) -> Result<Self, CsvError> {
+        let (header, columns, frames) = wide_columns(text, separator)?;
+        for (n, cell) in header.iter().enumerate() {
+            if number::<usize>(1, cell)? != n {
+                return Err(CsvError::Expected(1, "sets in order"));
+            }
+        }
+        Ok(RawMotion {
+            sets: columns.iter().map(|c| fit_column(c, tolerance)).collect(),
+            bones,
+            frames,
+        })
+    }
+
+    ///One row per keyframe with the set index as the channel, reading back to the same motion
+    pub fn to_csv_long(&self, separator: char) -> String {
+        let s = separator;
+        let mut out = join(
+            &LONG_HEADER
+                .iter()
+                .map(|x| x.to_string())
+                .collect::<Vec<_>>(),
+            s,
+        );
+        let _ = writeln!(out, "{}raw{}{}{}{}", s, s, s, self.frames, s);
+        for bone in &self.bones {
+            let _ = writeln!(out, "{}bone{}{}{}{}", s, s, s, bone, s);
+        }
+        for (i, set) in self.sets.iter().enumerate() {
+            write_curve(&mut out, &i.to_string(), set, s);
+        }
+        out
+    }
+
+    pub fn from_csv_long(text: &str, separator: char) -> Result<Self, CsvError> {
+        let mut raw = RawMotion::default();
+        for (i, cells) in long_rows(text, separator)? {
+            let (channel, ty) = match &cells[..] {
+                [channel, ty, ..] => (*channel, *ty),
+                _ => return Err(CsvError::Expected(i, "a channel and a type")),
+            };
+            let value = cells.get(3).copied().unwrap_or_default();
+            match ty {
+                "raw" => raw.frames = number(i, value)?,
+                "bone" => raw.bones.push(number(i, value)?),
+                _ => {
+                    let set: usize = number(i, channel)?;
+                    if set > raw.sets.len() {
+                        return Err(CsvError::Expected(i, "sets in order"));
+                    }
+                    if set == raw.sets.len() {
+                        raw.sets.push(FrameData::None);
+                    }
+                    read_curve(&mut raw.sets[set], i, ty, &cells)?;
+                }
+            }
+        }
+        Ok(raw)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn keys() -> FrameData {
+        FrameData::CatmulRom(vec![
+            Keyframe {
+                frame: 0,
+                value: 0.,
+                interpolation: (),
+            },
+            Keyframe {
+                frame: 4,
+                value: 1. / 3.,
+                interpolation: (),
+            },
+        ])
+    }
+
+    fn motion() -> Motion<'static> {
+        let mut anims = BTreeMap::new();
+        anims.insert(
+            Bone("n_hara_cp".into()),
+            Some(BoneAnim::PositionRotation {
+                position: (FrameData::Pose(0.5), FrameData::Pose(1.), keys()),
+                rotation: (
+                    FrameData::Hermite(vec![Keyframe {
+                        frame: 2,
+                        value: 0.25,
+                        interpolation: -0.5,
+                    }]),
+                    FrameData::Pose(0.),
+                    FrameData::Pose(0.),
+                ),
+            }),
+        );
+        anims.insert(
+            Bone("c_kata_l".into()),
+            Some(BoneAnim::ArmIk {
+                target: (keys(), FrameData::Pose(0.), FrameData::Pose(0.)),
+                rotation: (
+                    FrameData::Pose(0.),
+                    FrameData::Hermite(vec![]),
+                    FrameData::Pose(0.),
+                ),
+            }),
+        );
+        anims.insert(Bone("kl_kubi".into()), None);
+        Motion { frames: 5, anims }
+    }
+
+    #[test]
+    fn long_roundtrip() -> anyhow::Result<()> {
+        let mot = motion();
+        let csv = mot.to_csv_long(',');
+        assert!(csv.contains("\nn_hara_cp.rotation.x,hermite,2,0.25,-0.5\n"));
+        assert_eq!(Motion::from_csv_long(&csv, ',')?, mot);
+
+        let raw = RawMotion {
+            sets: vec![keys(), FrameData::None, FrameData::Pose(2.)],
+            bones: vec![3, 17],
+            frames: 5,
+        };
+        let tsv = raw.to_csv_long('\t');
+        assert!(tsv.contains(
+            "\n\tbone\t\t17\t\n0\tcatmull_rom\t\t\t\n0\tcatmull_rom\t0\t0\t\n0\tcatmull_rom\t4\t0.33333334\t\n"
+        ));
+        assert_eq!(RawMotion::from_csv_long(&tsv, '\t')?, raw);
+        Ok(())
+    }
+
+    #[test]
+    fn wide_roundtrip() -> anyhow::Result<()> {
+        let mot = motion();
+        let csv = mot.to_csv_wide(',');
+        let mut lines = csv.lines();
+        assert_eq!(
+            lines.next().unwrap().split(',').nth(1),
+            Some("n_hara_cp.position.x")
+        );
+        assert_eq!(lines.count(), 5);
+
+        let skeleton = Skeleton::test(&[
+            ("n_hara_cp", BoneType::Type3, None, &[[0.; 3]]),
+            ("c_kata_l", BoneType::Type5, None, &[[0.; 3]]),
+        ]);
+        let back = Motion::from_csv_wide(&csv, ',', &skeleton, 1e-4)?;
+        assert!(matches!(
+            back.anim("c_kata_l"),
+            Some(BoneAnim::ArmIk { .. })
+        ));
+        for (path, data) in mot.channels() {
+            let other = back.get(&path).unwrap();
+            for f in 0..5 {
+                let (a, b) = (data.evaluate(f as f32), other.evaluate(f as f32));
+                match (a, b) {
+                    (Some(a), Some(b)) => assert!((a - b).abs() < 1e-3, "{} at {}", path, f),
+                    (a, b) => assert_eq!(a, b, "{} at {}", path, f),
+                }
+            }
+        }
+        //Without a skeleton a target and a rotation could be either IK type
+        assert_eq!(
+            Motion::from_csv_wide(&csv, ',', &Skeleton::default(), 1e-4),
+            Err(CsvError::NoAnimation("c_kata_l".to_string()))
+        );
+        let sorted = csv.replacen("\n0,", "\n9,", 1);
+        assert_eq!(
+            Motion::from_csv_wide(&sorted, ',', &skeleton, 1e-4),
+            Err(CsvError::Expected(2, "frames counting up from 0"))
+        );
+
+        let raw = RawMotion {
+            sets: vec![keys(), FrameData::None, FrameData::Pose(2.)],
+            bones: vec![3, 17],
+            frames: 5,
+        };
+        let tsv = raw.to_csv_wide('\t');
+        let back = RawMotion::from_csv_wide(&tsv, '\t', vec![3, 17], 1e-4)?;
+        assert_eq!(back.bones, raw.bones);
+        assert_eq!(back.frames, 5);
+        assert_eq!(back.sets[1..], raw.sets[1..]);
+        for f in 0..5 {
+            let (a, b) = (
+                raw.sets[0].evaluate(f as f32),
+                back.sets[0].evaluate(f as f32),
+            );
+            assert!((a.unwrap() - b.unwrap()).abs() < 1e-3);
+        }
+        let empty = RawMotion::from_csv_wide("frame\n0\n1\n2\n", ',', vec![], 1e-4)?;
+        assert_eq!(empty.frames, 3);
+        assert!(empty.sets.is_empty());
+        Ok(())
+    }
+}
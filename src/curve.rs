@@ -0,0 +1,478 @@
+use super::*;
+
+#[derive(Copy, Clone, PartialEq, Eq, PartialOrd, Ord, Debug, Error)]
+pub enum KeyframeError {
+    #[error("Frame data has no keyframes")]
+    NotKeyed,
+    #[error("No keyframe at frame {0}")]
+    NoKey(u16),
+    #[error("A keyframe already exists at frame {0}")]
+    Occupied(u16),
+}
+
+impl FrameData {
+    ///Returns the frames of every key, or an empty vec if not keyed
+    pub fn key_frames(&self) -> Vec<u16> {
+        match self {
+            FrameData::None | FrameData::Pose(_) => vec![],
+            FrameData::CatmulRom(v) => v.iter().map(|k| k.frame).collect(),
+            FrameData::Hermite(v) => v.iter().map(|k| k.frame).collect(),
+        }
+    }
+
+    pub fn is_keyed(&self) -> bool {
+        matches!(self, FrameData::CatmulRom(_) | FrameData::Hermite(_))
+    }
+
+    ///Checks that keys are sorted and no two keys share a frame
+    pub fn is_valid(&self) -> bool {
+        let frames = self.key_frames();
+        frames.windows(2).all(|w| w[0] < w[1])
+    }
+
+    ///Sorts keys by frame, keeping the last of any keys that share a frame
+    pub fn normalize(&mut self) {
+        match self {
+            FrameData::None | FrameData::Pose(_) => {}
+            FrameData::CatmulRom(v) => normalize_keys(v),
+            FrameData::Hermite(v) => normalize_keys(v),
+        }
+    }
+
+    ///Turns a `Pose` into a single key at frame 0. `None` stays `None`
+    pub fn into_keyed(self) -> Self {
+        match self {
+            FrameData::Pose(value) => FrameData::CatmulRom(vec![Keyframe {
+                frame: 0,
+                value,
+                interpolation: (),
+            }]),
+            x => x,
+        }
+    }
+
+    ///Turns a curve holding a constant value into a `Pose`, otherwise returns the curve back
+    pub fn into_pose(self) -> Result<Self, Self> {
+        let value = match &self {
+            FrameData::None => return Err(self),
+            FrameData::Pose(_) => return Ok(self),
+            FrameData::CatmulRom(v) => match v.first() {
+                Some(first) if v.iter().all(|k| k.value == first.value) => first.value,
+                _ => return Err(self),
+            },
+            FrameData::Hermite(v) => match v.first() {
+                Some(first)
+                    if v
+                        .iter()
+                        .all(|k| k.value == first.value && k.interpolation == 0.) =>
+                {
+                    first.value
+                }
+                _ => return Err(self),
+            },
+        };
+        Ok(FrameData::Pose(value))
+    }
+
+    ///Inserts a key or replaces the value of the key at `frame`.
+    ///
+    ///`None` and `Pose` are turned into a curve first. Keys inserted into a `Hermite` curve get the
+    ///same tangent a Catmull-Rom curve would have at that point.
+    pub fn insert_key(&mut self, frame: u16, value: f32) {
+        match self {
+            FrameData::None => {
+                *self = FrameData::CatmulRom(vec![Keyframe {
+                    frame,
+                    value,
+                    interpolation: (),
+                }])
+            }
+            FrameData::Pose(_) => {
+                *self = core::mem::replace(self, FrameData::None).into_keyed();
+                self.insert_key(frame, value);
+            }
+            FrameData::CatmulRom(v) => match v.binary_search_by_key(&frame, |k| k.frame) {
+                Ok(i) => v[i].value = value,
+                Err(i) => v.insert(
+                    i,
+                    Keyframe {
+                        frame,
+                        value,
+                        interpolation: (),
+                    },
+                ),
+            },
+            FrameData::Hermite(v) => match v.binary_search_by_key(&frame, |k| k.frame) {
+                Ok(i) => v[i].value = value,
+                Err(i) => {
+                    v.insert(
+                        i,
+                        Keyframe {
+                            frame,
+                            value,
+                            interpolation: 0.,
+                        },
+                    );
+                    v[i].interpolation = catmull_rom_tangent(v, i);
+                }
+            },
+        }
+    }
+
+    ///Removes the key at `frame`, returning its value
+    pub fn remove_key(&mut self, frame: u16) -> Result<f32, KeyframeError> {
+        match self {
+            FrameData::None | FrameData::Pose(_) => Err(KeyframeError::NotKeyed),
+            FrameData::CatmulRom(v) => remove_key(v, frame).map(|k| k.value),
+            FrameData::Hermite(v) => remove_key(v, frame).map(|k| k.value),
+        }
+    }
+
+    ///Moves the key at `from` to `to`, keeping its value and tangent
+    pub fn move_key(&mut self, from: u16, to: u16) -> Result<(), KeyframeError> {
+        match self {
+            FrameData::None | FrameData::Pose(_) => Err(KeyframeError::NotKeyed),
+            FrameData::CatmulRom(v) => move_key(v, from, to),
+            FrameData::Hermite(v) => move_key(v, from, to),
+        }
+    }
+
+    ///Sets the value of the key at `frame`. A `Pose` has its value set regardless of `frame`
+    pub fn set_value(&mut self, frame: u16, value: f32) -> Result<(), KeyframeError> {
+        match self {
+            FrameData::None => Err(KeyframeError::NotKeyed),
+            FrameData::Pose(p) => {
+                *p = value;
+                Ok(())
+            }
+            FrameData::CatmulRom(v) => find_key(v, frame).map(|k| k.value = value),
+            FrameData::Hermite(v) => find_key(v, frame).map(|k| k.value = value),
+        }
+    }
+
+    ///Sets the tangent of the key at `frame`, promoting a `CatmulRom` curve to `Hermite`
+    pub fn set_tangent(&mut self, frame: u16, tangent: f32) -> Result<(), KeyframeError> {
+        match self {
+            FrameData::None | FrameData::Pose(_) => Err(KeyframeError::NotKeyed),
+            FrameData::CatmulRom(v) => {
+                //Check before promoting so a failed edit leaves the curve untouched
+                find_key(v, frame)?;
+                *self = core::mem::replace(self, FrameData::None).into_hermite();
+                self.set_tangent(frame, tangent)
+            }
+            FrameData::Hermite(v) => find_key(v, frame).map(|k| k.interpolation = tangent),
+        }
+    }
+
+    ///Multiplies every value and tangent by `factor`
+    pub fn scaled(&self, factor: f32) -> Self {
+        match self {
+            FrameData::None => FrameData::None,
+            FrameData::Pose(p) => FrameData::Pose(p * factor),
+            FrameData::CatmulRom(v) => FrameData::CatmulRom(
+                v.iter()
+                    .map(|k| Keyframe {
+                        value: k.value * factor,
+                        ..*k
+                    })
+                    .collect(),
+            ),
+            FrameData::Hermite(v) => FrameData::Hermite(
+                v.iter()
+                    .map(|k| Keyframe {
+                        value: k.value * factor,
+                        interpolation: k.interpolation * factor,
+                        ..*k
+                    })
+                    .collect(),
+            ),
+        }
+    }
+
+    ///Adds `delta` to every value. Missing data counts as 0
+    pub fn translated(&self, delta: f32) -> Self {
+        match self {
+            FrameData::None if delta == 0. => FrameData::None,
+            FrameData::None => FrameData::Pose(delta),
+            FrameData::Pose(p) => FrameData::Pose(p + delta),
+            FrameData::CatmulRom(v) => FrameData::CatmulRom(
+                v.iter()
+                    .map(|k| Keyframe {
+                        value: k.value + delta,
+                        ..*k
+                    })
+                    .collect(),
+            ),
+            FrameData::Hermite(v) => FrameData::Hermite(
+                v.iter()
+                    .map(|k| Keyframe {
+                        value: k.value + delta,
+                        ..*k
+                    })
+                    .collect(),
+            ),
+        }
+    }
+
+    ///Samples the curve at `frame`, holding the first and last values outside the keyed range
+    pub fn evaluate(&self, frame: f32) -> Option<f32> {
+        match self {
+            FrameData::None => None,
+            FrameData::Pose(p) => Some(*p),
+            FrameData::CatmulRom(v) => evaluate_keys(v, frame, catmull_rom_tangent),
+            FrameData::Hermite(v) => evaluate_keys(v, frame, |v, i| v[i].interpolation),
+        }
+    }
+
+    ///The rate of change per frame of the curve at `frame`
+    pub fn slope(&self, frame: f32) -> Option<f32> {
+        match self {
+            FrameData::None => None,
+            FrameData::Pose(_) => Some(0.),
+            FrameData::CatmulRom(v) => sample_keys(v, frame, catmull_rom_tangent).map(|(_, s)| s),
+            FrameData::Hermite(v) => {
+                sample_keys(v, frame, |v, i| v[i].interpolation).map(|(_, s)| s)
+            }
+        }
+    }
+
+    ///Converts a `CatmulRom` curve into an identical `Hermite` curve with explicit tangents
+    pub fn into_hermite(self) -> Self {
+        match self {
+            FrameData::CatmulRom(v) => {
+                let keys = (0..v.len())
+                    .map(|i| Keyframe {
+                        frame: v[i].frame,
+                        value: v[i].value,
+                        interpolation: catmull_rom_tangent(&v, i),
+                    })
+                    .collect();
+                FrameData::Hermite(keys)
+            }
+            x => x,
+        }
+    }
+
+    ///Drops the tangents of a `Hermite` curve if the resulting `CatmulRom` curve stays within
+    ///`tolerance` of it on every frame, otherwise returns the curve back
+    pub fn into_catmull_rom(self, tolerance: f32) -> Result<Self, Self> {
+        let v = match &self {
+            FrameData::Hermite(v) => v,
+            _ => return Ok(self),
+        };
+        let keys: Vec<_> = v
+            .iter()
+            .map(|k| Keyframe {
+                frame: k.frame,
+                value: k.value,
+                interpolation: (),
+            })
+            .collect();
+        let (first, last) = match (v.first(), v.last()) {
+            (Some(first), Some(last)) => (first.frame, last.frame),
+            _ => return Ok(FrameData::CatmulRom(keys)),
+        };
+        let candidate = FrameData::CatmulRom(keys);
+        let fits = (first..=last).all(|f| {
+            let a = self.evaluate(f as f32).unwrap_or_default();
+            let b = candidate.evaluate(f as f32).unwrap_or_default();
+            (a - b).abs() <= tolerance
+        });
+        if fits {
+            Ok(candidate)
+        } else {
+            Err(self)
+        }
+    }
+}
+
+fn evaluate_keys<I, F>(keys: &[Keyframe<I>], frame: f32, tangent: F) -> Option<f32>
+where
+    F: Fn(&[Keyframe<I>], usize) -> f32,
+{
+    sample_keys(keys, frame, tangent).map(|(value, _)| value)
+}
+
+///Returns the value and slope of the curve at `frame`
+fn sample_keys<I, F>(keys: &[Keyframe<I>], frame: f32, tangent: F) -> Option<(f32, f32)>
+where
+    F: Fn(&[Keyframe<I>], usize) -> f32,
+{
+    sample_split_keys(keys, frame, |keys, i| {
+        let t = tangent(keys, i);
+        (t, t)
+    })
+}
+
+///Like [`sample_keys`] for keys with separate incoming and outgoing slopes
+pub(crate) fn sample_split_keys<I, F>(
+    keys: &[Keyframe<I>],
+    frame: f32,
+    tangents: F,
+) -> Option<(f32, f32)>
+where
+    F: Fn(&[Keyframe<I>], usize) -> (f32, f32),
+{
+    let first = keys.first()?;
+    let last = keys.last()?;
+    if frame < first.frame as f32 {
+        return Some((first.value, 0.));
+    }
+    if frame > last.frame as f32 {
+        return Some((last.value, 0.));
+    }
+    if frame == last.frame as f32 {
+        return Some((last.value, tangents(keys, keys.len() - 1).0));
+    }
+    //`frame` lies inside the keyed range before the last key, so there is a key after it
+    let i = keys.iter().position(|k| k.frame as f32 > frame)? - 1;
+    let (k0, k1) = (&keys[i], &keys[i + 1]);
+    let dt = k1.frame as f32 - k0.frame as f32;
+    let (m0, m1) = (tangents(keys, i).1 * dt, tangents(keys, i + 1).0 * dt);
+    let t = (frame - k0.frame as f32) / dt;
+    let (t2, t3) = (t * t, t * t * t);
+    let h00 = 2. * t3 - 3. * t2 + 1.;
+    let h10 = t3 - 2. * t2 + t;
+    let h01 = -2. * t3 + 3. * t2;
+    let h11 = t3 - t2;
+    let value = h00 * k0.value + h10 * m0 + h01 * k1.value + h11 * m1;
+    let d00 = 6. * t2 - 6. * t;
+    let d10 = 3. * t2 - 4. * t + 1.;
+    let d11 = 3. * t2 - 2. * t;
+    let slope = (d00 * (k0.value - k1.value) + d10 * m0 + d11 * m1) / dt;
+    Some((value, slope))
+}
+
+///The slope a Catmull-Rom curve has at the `i`th key, using one sided differences at the ends
+pub(crate) fn catmull_rom_tangent<I>(keys: &[Keyframe<I>], i: usize) -> f32 {
+    let prev = &keys[i.saturating_sub(1)];
+    let next = &keys[(i + 1).min(keys.len() - 1)];
+    if next.frame == prev.frame {
+        return 0.;
+    }
+    (next.value - prev.value) / (next.frame as f32 - prev.frame as f32)
+}
+
+fn normalize_keys<I>(keys: &mut Vec<Keyframe<I>>) {
+    //Stable sort keeps insertion order, so the last key of a frame wins after reversing
+    keys.sort_by_key(|k| k.frame);
+    keys.reverse();
+    keys.dedup_by_key(|k| k.frame);
+    keys.reverse();
+}
+
+fn find_key<I>(keys: &mut [Keyframe<I>], frame: u16) -> Result<&mut Keyframe<I>, KeyframeError> {
+    keys.binary_search_by_key(&frame, |k| k.frame)
+        .map(move |i| &mut keys[i])
+        .map_err(|_| KeyframeError::NoKey(frame))
+}
+
+fn remove_key<I>(keys: &mut Vec<Keyframe<I>>, frame: u16) -> Result<Keyframe<I>, KeyframeError> {
+    keys.binary_search_by_key(&frame, |k| k.frame)
+        .map(|i| keys.remove(i))
+        .map_err(|_| KeyframeError::NoKey(frame))
+}
+
+fn move_key<I>(keys: &mut Vec<Keyframe<I>>, from: u16, to: u16) -> Result<(), KeyframeError> {
+    if from == to {
+        return find_key(keys, from).map(|_| ());
+    }
+    let dest = match keys.binary_search_by_key(&to, |k| k.frame) {
+        Ok(_) => return Err(KeyframeError::Occupied(to)),
+        Err(i) => i,
+    };
+    let src = keys
+        .binary_search_by_key(&from, |k| k.frame)
+        .map_err(|_| KeyframeError::NoKey(from))?;
+    let mut key = keys.remove(src);
+    key.frame = to;
+    //Removing the source shifts every later slot down by one
+    let dest = if src < dest { dest - 1 } else { dest };
+    keys.insert(dest, key);
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn curve() -> FrameData {
+        let mut data = FrameData::None;
+        data.insert_key(10, 1.);
+        data.insert_key(0, 0.);
+        data.insert_key(20, 0.);
+        data
+    }
+
+    #[test]
+    fn insert_sorted() {
+        let mut data = curve();
+        assert_eq!(data.key_frames(), vec![0, 10, 20]);
+        data.insert_key(10, 2.);
+        assert_eq!(data.key_frames(), vec![0, 10, 20]);
+        assert!(data.is_valid());
+    }
+
+    #[test]
+    fn move_and_remove() -> anyhow::Result<()> {
+        let mut data = curve();
+        data.move_key(0, 15)?;
+        assert_eq!(data.key_frames(), vec![10, 15, 20]);
+        assert_eq!(data.move_key(10, 20), Err(KeyframeError::Occupied(20)));
+        assert_eq!(data.remove_key(15)?, 0.);
+        assert_eq!(data.remove_key(15), Err(KeyframeError::NoKey(15)));
+        assert_eq!(data.key_frames(), vec![10, 20]);
+        Ok(())
+    }
+
+    #[test]
+    fn tangent_promotes() -> anyhow::Result<()> {
+        let mut data = curve();
+        data.set_tangent(10, 0.5)?;
+        match &data {
+            FrameData::Hermite(v) => {
+                assert_eq!(v[0].interpolation, 0.1);
+                assert_eq!(v[1].interpolation, 0.5);
+            }
+            _ => panic!("Expected hermite curve"),
+        }
+        assert_eq!(
+            FrameData::Pose(1.).set_tangent(0, 1.),
+            Err(KeyframeError::NotKeyed)
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn hermite_conversion() {
+        let data = curve();
+        let hermite = data.clone().into_hermite();
+        for f in 0..=20 {
+            let f = f as f32 + 0.5;
+            assert!((data.evaluate(f).unwrap() - hermite.evaluate(f).unwrap()).abs() < 1e-6);
+        }
+        assert_eq!(hermite.clone().into_catmull_rom(1e-6), Ok(data));
+
+        let mut edited = hermite;
+        edited.set_tangent(10, 1.).unwrap();
+        assert!(edited.clone().into_catmull_rom(1e-3).is_err());
+        assert!(edited.into_catmull_rom(10.).is_ok());
+    }
+
+    #[test]
+    fn evaluate_holds_ends() {
+        let data = curve();
+        assert_eq!(data.evaluate(-5.), Some(0.));
+        assert_eq!(data.evaluate(10.), Some(1.));
+        assert_eq!(data.evaluate(30.), Some(0.));
+        assert_eq!(FrameData::None.evaluate(0.), None);
+    }
+
+    #[test]
+    fn pose_roundtrip() {
+        let data = FrameData::Pose(3.).into_keyed();
+        assert_eq!(data.key_frames(), vec![0]);
+        assert_eq!(data.into_pose(), Ok(FrameData::Pose(3.)));
+        assert!(curve().into_pose().is_err());
+    }
+}
@@ -0,0 +1,755 @@
+use super::*;
+use crate::blend::{fit, sample, to_euler};
+use crate::channel::Component;
+use crate::math::{Quat, Transform, Vector3};
+use crate::skeleton::Skeleton;
+use diva_db::bone::BoneType;
+use serde_json::{json, Value};
+
+use core::convert::TryFrom;
+
+#[derive(Debug, Error)]
+pub enum GltfError {
+    #[error("Invalid glTF JSON")]
+    Json(#[from] serde_json::Error),
+    #[error("Not a glTF 2.0 binary file")]
+    NotGlb,
+    #[error("Buffer is not embedded as base64")]
+    BadUri,
+    #[error("Only a single buffer is supported")]
+    MultipleBuffers,
+    #[error("Accessor `{0}` is invalid or out of bounds")]
+    BadAccessor(usize),
+    #[error("No animation `{0}`")]
+    NoAnimation(usize),
+    #[error("Animation is too long to fit in a motion")]
+    TooLong,
+}
+
+///A glTF 2.0 document with its binary buffer
+#[derive(Clone, PartialEq, Debug)]
+pub struct Gltf {
+    pub json: Value,
+    pub buffer: Vec<u8>,
+}
+
+const BASE64: &[u8; 64] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+
+pub(crate) fn base64_encode(data: &[u8]) -> String {
+    let mut out = String::with_capacity(data.len().div_ceil(3) * 4);
+    for chunk in data.chunks(3) {
+        let b = [
+            chunk[0],
+            *chunk.get(1).unwrap_or(&0),
+            *chunk.get(2).unwrap_or(&0),
+        ];
+        let n = (b[0] as u32) << 16 | (b[1] as u32) << 8 | b[2] as u32;
+        for i in 0..4 {
+            if i <= chunk.len() {
+                out.push(BASE64[(n >> (18 - 6 * i) & 63) as usize] as char);
+            } else {
+                out.push('=');
+            }
+        }
+    }
+    out
+}
+
+pub(crate) fn base64_decode(text: &str) -> Option<Vec<u8>> {
+    let mut out = Vec::with_capacity(text.len() / 4 * 3);
+    let (mut acc, mut bits) = (0u32, 0);
+    for c in text.bytes().filter(|&c| c != b'=') {
+        let v = BASE64.iter().position(|&x| x == c)? as u32;
+        acc = acc << 6 | v;
+        bits += 6;
+        if bits >= 8 {
+            bits -= 8;
+            out.push((acc >> bits) as u8);
+        }
+    }
+    Some(out)
+}
+
+impl Gltf {
+    ///Reads a `.gltf` file. Buffers embedded as data URIs are decoded, otherwise `buffer` is
+    ///left empty for the caller to fill from the file it points at.
+    pub fn from_gltf(text: &str) -> Result<Self, GltfError> {
+        let json: Value = serde_json::from_str(text)?;
+        let buffers = json["buffers"].as_array().map(Vec::len).unwrap_or_default();
+        if buffers > 1 {
+            return Err(GltfError::MultipleBuffers);
+        }
+        let buffer = match json["buffers"][0]["uri"].as_str() {
+            Some(uri) if uri.starts_with("data:") => {
+                let data = uri
+                    .split_once(";base64,")
+                    .map(|(_, d)| d)
+                    .ok_or(GltfError::BadUri)?;
+                base64_decode(data).ok_or(GltfError::BadUri)?
+            }
+            _ => vec![],
+        };
+        Ok(Self { json, buffer })
+    }
+
+    ///Reads a binary `.glb` file
+    pub fn from_glb(data: &[u8]) -> Result<Self, GltfError> {
+        let u32_at = |i: usize| -> Result<usize, GltfError> {
+            let bytes = data.get(i..i + 4).ok_or(GltfError::NotGlb)?;
+            Ok(u32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]) as usize)
+        };
+        if data.get(0..4) != Some(b"glTF") || u32_at(4)? != 2 {
+            return Err(GltfError::NotGlb);
+        }
+        let mut json = None;
+        let mut buffer = vec![];
+        let mut at = 12;
+        while at + 8 <= data.len() {
+            let len = u32_at(at)?;
+            let chunk = data.get(at + 8..at + 8 + len).ok_or(GltfError::NotGlb)?;
+            match &data[at + 4..at + 8] {
+                b"JSON" => json = Some(serde_json::from_slice(chunk)?),
+                b"BIN\0" => buffer = chunk.to_vec(),
+                _ => (),
+            }
+            at += 8 + len;
+        }
+        let json: Value = json.ok_or(GltfError::NotGlb)?;
+        if json["buffers"].as_array().map(Vec::len).unwrap_or_default() > 1 {
+            return Err(GltfError::MultipleBuffers);
+        }
+        Ok(Self { json, buffer })
+    }
+
+    ///Reads accessor `index` as floats, normalizing integer components
+    fn accessor(&self, index: usize) -> Result<Vec<f32>, GltfError> {
+        let bad = || GltfError::BadAccessor(index);
+        let accessor = &self.json["accessors"][index];
+        let view =
+            &self.json["bufferViews"][accessor["bufferView"].as_u64().ok_or_else(bad)? as usize];
+        if view["buffer"].as_u64().unwrap_or_default() != 0 {
+            return Err(GltfError::MultipleBuffers);
+        }
+        let width = match accessor["type"].as_str().ok_or_else(bad)? {
+            "SCALAR" => 1,
+            "VEC2" => 2,
+            "VEC3" => 3,
+            "VEC4" => 4,
+            "MAT4" => 16,
+            _ => return Err(bad()),
+        };
+        let kind = accessor["componentType"].as_u64().ok_or_else(bad)?;
+        let size = match kind {
+            5120 | 5121 => 1,
+            5122 | 5123 => 2,
+            5126 => 4,
+            _ => return Err(bad()),
+        };
+        let count = accessor["count"].as_u64().ok_or_else(bad)? as usize;
+        let start = (view["byteOffset"].as_u64().unwrap_or_default() as usize)
+            .checked_add(accessor["byteOffset"].as_u64().unwrap_or_default() as usize)
+            .ok_or_else(bad)?;
+        let stride = match view["byteStride"].as_u64() {
+            Some(stride) => stride as usize,
+            None => width * size,
+        };
+        //Check the last element lies inside the buffer before allocating for `count` of them
+        if let Some(last) = count.checked_sub(1) {
+            let end = stride
+                .checked_mul(last)
+                .and_then(|x| x.checked_add(start))
+                .and_then(|x| x.checked_add(width * size))
+                .ok_or_else(bad)?;
+            if end > self.buffer.len() {
+                return Err(bad());
+            }
+        }
+        let mut out = Vec::with_capacity(count * width);
+        for i in 0..count {
+            for c in 0..width {
+                let at = start + i * stride + c * size;
+                let b = self.buffer.get(at..at + size).ok_or_else(bad)?;
+                out.push(match kind {
+                    5120 => (b[0] as i8 as f32 / 127.).max(-1.),
+                    5121 => b[0] as f32 / 255.,
+                    5122 => (i16::from_le_bytes([b[0], b[1]]) as f32 / 32767.).max(-1.),
+                    5123 => u16::from_le_bytes([b[0], b[1]]) as f32 / 65535.,
+                    _ => f32::from_le_bytes([b[0], b[1], b[2], b[3]]),
+                });
+            }
+        }
+        Ok(out)
+    }
+    ///A `.gltf` file with the buffer embedded as a data URI
+    pub fn to_gltf(&self) -> String {
+        let mut json = self.json.clone();
+        json["buffers"][0]["uri"] = format!(
+            "data:application/octet-stream;base64,{}",
+            base64_encode(&self.buffer)
+        )
+        .into();
+        json.to_string()
+    }
+
+    ///A binary `.glb` file
+    pub fn to_glb(&self) -> Vec<u8> {
+        let pad = |mut x: Vec<u8>, with: u8| {
+            x.resize(x.len().div_ceil(4) * 4, with);
+            x
+        };
+        let json = pad(self.json.to_string().into_bytes(), b' ');
+        let bin = pad(self.buffer.clone(), 0);
+        let len = 12 + 8 + json.len() + 8 + bin.len();
+        let mut out = Vec::with_capacity(len);
+        out.extend_from_slice(b"glTF");
+        out.extend_from_slice(&2u32.to_le_bytes());
+        out.extend_from_slice(&(len as u32).to_le_bytes());
+        out.extend_from_slice(&(json.len() as u32).to_le_bytes());
+        out.extend_from_slice(b"JSON");
+        out.extend_from_slice(&json);
+        out.extend_from_slice(&(bin.len() as u32).to_le_bytes());
+        out.extend_from_slice(b"BIN\0");
+        out.extend_from_slice(&bin);
+        out
+    }
+}
+
+///Lays out accessors back to back in a single buffer
+#[derive(Default)]
+struct Builder {
+    buffer: Vec<u8>,
+    views: Vec<Value>,
+    accessors: Vec<Value>,
+}
+
+impl Builder {
+    fn push(&mut self, data: &[f32], kind: &str, width: usize) -> usize {
+        let offset = self.buffer.len();
+        for x in data {
+            self.buffer.extend_from_slice(&x.to_le_bytes());
+        }
+        self.views.push(json!({
+            "buffer": 0,
+            "byteOffset": offset,
+            "byteLength": data.len() * 4,
+        }));
+        let mut accessor = json!({
+            "bufferView": self.views.len() - 1,
+            "componentType": 5126,
+            "count": data.len() / width,
+            "type": kind,
+        });
+        //Animation inputs must have bounds
+        if kind == "SCALAR" && !data.is_empty() {
+            let min = data.iter().copied().fold(f32::INFINITY, f32::min);
+            let max = data.iter().copied().fold(f32::NEG_INFINITY, f32::max);
+            accessor["min"] = json!([min]);
+            accessor["max"] = json!([max]);
+        }
+        self.accessors.push(accessor);
+        self.accessors.len() - 1
+    }
+}
+
+///The incoming and outgoing slope of `data` at `frame`, which differ past the keyed range
+fn tangents(data: &FrameData, frame: u16) -> (f32, f32) {
+    let slope = data.slope(frame as f32).unwrap_or_default();
+    let keys = data.key_frames();
+    match (keys.first(), keys.last()) {
+        (Some(&first), Some(&last)) => (
+            if frame > first { slope } else { 0. },
+            if frame < last { slope } else { 0. },
+        ),
+        _ => (0., 0.),
+    }
+}
+
+///Turns a position into a `CUBICSPLINE` sampler keyed on every frame any axis is keyed on.
+///
+///Splitting a Hermite segment with its own slope keeps the curve identical, so this is exact.
+fn cubic_spline(vec: &Vec3, fps: f32) -> (Vec<f32>, Vec<f32>) {
+    let axes = [&vec.0, &vec.1, &vec.2];
+    let axes: Vec<FrameData> = axes
+        .iter()
+        .map(|x| match x {
+            FrameData::CatmulRom(_) => (*x).clone().into_hermite(),
+            _ => (*x).clone(),
+        })
+        .collect();
+    let mut frames: Vec<u16> = axes.iter().flat_map(|x| x.key_frames()).collect();
+    frames.sort_unstable();
+    frames.dedup();
+    if frames.is_empty() {
+        frames.push(0);
+    }
+    let times = frames.iter().map(|&f| f as f32 / fps).collect();
+    let mut output = Vec::with_capacity(frames.len() * 9);
+    for &frame in &frames {
+        let values: Vec<f32> = axes
+            .iter()
+            .map(|x| x.evaluate(frame as f32).unwrap_or_default())
+            .collect();
+        let slopes: Vec<(f32, f32)> = axes.iter().map(|x| tangents(x, frame)).collect();
+        //Tangents are per second
+        output.extend(slopes.iter().map(|s| s.0 * fps));
+        output.extend(values);
+        output.extend(slopes.iter().map(|s| s.1 * fps));
+    }
+    (times, output)
+}
+
+///An animation sampler read into memory
+struct Sampler {
+    times: Vec<f32>,
+    values: Vec<f32>,
+    interpolation: String,
+    width: usize,
+}
+
+impl Sampler {
+    fn value(&self, i: usize) -> &[f32] {
+        let i = if self.interpolation == "CUBICSPLINE" {
+            i * 3 + 1
+        } else {
+            i
+        };
+        &self.values[i * self.width..(i + 1) * self.width]
+    }
+
+    fn sample(&self, time: f32) -> Vec<f32> {
+        let last = self.times.len() - 1;
+        let i = match self.times.iter().position(|&t| t > time) {
+            Some(0) => return self.value(0).to_vec(),
+            None => return self.value(last).to_vec(),
+            Some(i) => i - 1,
+        };
+        let td = self.times[i + 1] - self.times[i];
+        let t = (time - self.times[i]) / td;
+        let (a, b) = (self.value(i), self.value(i + 1));
+        let mut out: Vec<f32> = match &self.interpolation[..] {
+            "STEP" => a.to_vec(),
+            "CUBICSPLINE" => {
+                let w = self.width;
+                let out_tangent = &self.values[(i * 3 + 2) * w..(i * 3 + 3) * w];
+                let in_tangent = &self.values[(i + 1) * 3 * w..((i + 1) * 3 + 1) * w];
+                let (t2, t3) = (t * t, t * t * t);
+                (0..w)
+                    .map(|c| {
+                        (2. * t3 - 3. * t2 + 1.) * a[c]
+                            + (t3 - 2. * t2 + t) * td * out_tangent[c]
+                            + (-2. * t3 + 3. * t2) * b[c]
+                            + (t3 - t2) * td * in_tangent[c]
+                    })
+                    .collect()
+            }
+            _ if self.width == 4 => {
+                let q = |x: &[f32]| Quat {
+                    x: x[0],
+                    y: x[1],
+                    z: x[2],
+                    w: x[3],
+                };
+                let q = q(a).slerp(q(b), t);
+                vec![q.x, q.y, q.z, q.w]
+            }
+            _ => a.iter().zip(b).map(|(a, b)| a + (b - a) * t).collect(),
+        };
+        if self.width == 4 {
+            let len = out.iter().map(|x| x * x).sum::<f32>().sqrt().max(1e-12);
+            out.iter_mut().for_each(|x| *x /= len);
+        }
+        out
+    }
+}
+
+impl<'a> Motion<'a> {
+    ///Imports animation `index` of `gltf` onto the bones of `skeleton`, sampled at `fps`.
+    ///
+    ///Nodes are matched to bones by name, so the glTF skeleton should be laid out like the one
+    ///from [`Motion::to_gltf`], with no rotation at rest. Every channel is sampled once per
+    ///frame and fitted to `tolerance`. Joints of IK chains are turned back into IK bones with
+    ///[`Motion::to_ik`]. Type 1 bones have no glTF equivalent and always come back as a
+    ///[`BoneAnim::Unk`] without curves.
+    pub fn from_gltf(
+        gltf: &Gltf,
+        skeleton: &Skeleton,
+        index: usize,
+        fps: f32,
+        tolerance: f32,
+    ) -> Result<Self, GltfError> {
+        let animation = gltf.json["animations"]
+            .get(index)
+            .ok_or(GltfError::NoAnimation(index))?;
+        let empty = vec![];
+        let nodes = gltf.json["nodes"].as_array().unwrap_or(&empty);
+        let fk = skeleton.without_ik();
+
+        let mut samplers = BTreeMap::new();
+        let mut end = 0f32;
+        for channel in animation["channels"].as_array().unwrap_or(&empty) {
+            let target = &channel["target"];
+            let path = match target["path"].as_str() {
+                Some(path @ "translation") | Some(path @ "rotation") => path,
+                _ => continue,
+            };
+            let node = match target["node"].as_u64() {
+                Some(node) => node as usize,
+                None => continue,
+            };
+            let sampler =
+                &animation["samplers"][channel["sampler"].as_u64().unwrap_or_default() as usize];
+            let accessor = |key: &str| match sampler[key].as_u64() {
+                Some(i) => gltf.accessor(i as usize),
+                None => Err(GltfError::BadAccessor(usize::MAX)),
+            };
+            let times = accessor("input")?;
+            let values = accessor("output")?;
+            let interpolation = sampler["interpolation"].as_str().unwrap_or("LINEAR");
+            let width = if path == "rotation" { 4 } else { 3 };
+            let keys = if interpolation == "CUBICSPLINE" { 3 } else { 1 };
+            if times.is_empty() || values.len() < times.len() * width * keys {
+                continue;
+            }
+            end = end.max(*times.last().unwrap());
+            samplers.insert(
+                (node, path),
+                Sampler {
+                    times,
+                    values,
+                    interpolation: interpolation.to_string(),
+                    width,
+                },
+            );
+        }
+
+        let frames = u16::try_from((end * fps).round() as i64)
+            .ok()
+            .and_then(|x| x.checked_add(1))
+            .ok_or(GltfError::TooLong)?;
+        let mut anims = BTreeMap::new();
+        for (i, node) in nodes.iter().enumerate() {
+            let name = match node["name"].as_str() {
+                Some(name) => name,
+                None => continue,
+            };
+            let mode = match name {
+                "gblctr" => BoneType::Position,
+                "kg_ya_ex" => BoneType::Rotation,
+                _ => match fk.get(name) {
+                    Some(bone) => bone.mode,
+                    None => continue,
+                },
+            };
+            if matches!(mode, BoneType::Type1) {
+                let none = || (FrameData::None, FrameData::None, FrameData::None);
+                let anim = BoneAnim::Unk(none(), none());
+                anims.insert(Bone(Cow::Owned(name.to_string())), Some(anim));
+                continue;
+            }
+            let translation = samplers.get(&(i, "translation"));
+            let rotation = samplers.get(&(i, "rotation"));
+            if translation.is_none() && rotation.is_none() {
+                continue;
+            }
+            let times = (0..frames).map(|f| f as f32 / fps);
+            //Values the node has when nothing animates it
+            let rest = |key: &str, len: usize| -> Option<Vec<f32>> {
+                let x = node[key].as_array().filter(|x| x.len() == len)?;
+                Some(
+                    x.iter()
+                        .map(|x| x.as_f64().unwrap_or_default() as f32)
+                        .collect(),
+                )
+            };
+            let positions = || -> Vec3 {
+                let rest = rest("translation", 3).map_or([0.; 3], |x| [x[0], x[1], x[2]]);
+                let samples: Vec<Vector3> = match translation {
+                    Some(s) => times
+                        .clone()
+                        .map(|t| {
+                            let v = s.sample(t);
+                            [v[0], v[1], v[2]]
+                        })
+                        .collect(),
+                    None => vec![rest],
+                };
+                fit(0, &samples, tolerance)
+            };
+            let rotations = || -> Vec3 {
+                let samples: Vec<Quat> = match rotation {
+                    Some(s) => times
+                        .clone()
+                        .map(|t| {
+                            let v = s.sample(t);
+                            Quat {
+                                x: v[0],
+                                y: v[1],
+                                z: v[2],
+                                w: v[3],
+                            }
+                        })
+                        .collect(),
+                    None => vec![rest("rotation", 4).map_or(Quat::IDENTITY, |x| Quat {
+                        x: x[0],
+                        y: x[1],
+                        z: x[2],
+                        w: x[3],
+                    })],
+                };
+                fit(0, &to_euler(&samples), tolerance)
+            };
+            let anim = match mode {
+                BoneType::Position => BoneAnim::Position(positions()),
+                BoneType::Type3 => BoneAnim::PositionRotation {
+                    position: positions(),
+                    rotation: rotations(),
+                },
+                _ => BoneAnim::Rotation(rotations()),
+            };
+            anims.insert(Bone(Cow::Owned(name.to_string())), Some(anim));
+        }
+        let mot = Motion { frames, anims };
+        Ok(mot.to_ik(skeleton, tolerance))
+    }
+
+    ///Exports the motion on `skeleton` as a glTF skin with one animation, playing at `fps`.
+    ///
+    ///IK bones are baked with [`Motion::bake_ik`] first since glTF has no IK. Positions keep
+    ///their curves as `CUBICSPLINE` samplers, while rotations are sampled on every frame since
+    ///glTF has no Euler curves. `gblctr` and `kg_ya_ex` become the two topmost nodes.
+    pub fn to_gltf(&self, skeleton: &Skeleton, fps: f32, tolerance: f32) -> Gltf {
+        let fk = skeleton.without_ik();
+        let mot = self.bake_ik(skeleton, tolerance);
+        let mut builder = Builder::default();
+
+        let mut nodes = vec![
+            json!({ "name": "gblctr", "children": [1] }),
+            json!({ "name": "kg_ya_ex", "children": [] }),
+        ];
+        let mut rest = vec![Transform::IDENTITY; 2];
+        for bone in &fk.bones {
+            let [x, y, z] = bone.rest();
+            nodes.push(json!({
+                "name": &bone.name[..],
+                "translation": [x, y, z],
+                "children": [],
+            }));
+            rest.push(Transform::new([x, y, z], Quat::IDENTITY));
+        }
+        for (i, bone) in fk.bones.iter().enumerate() {
+            let parent = bone.parent.map(|p| p + 2).unwrap_or(1);
+            nodes[parent]["children"]
+                .as_array_mut()
+                .unwrap()
+                .push(json!(i + 2));
+        }
+        //glTF doesn't allow empty lists
+        for node in nodes.iter_mut() {
+            if matches!(node["children"].as_array(), Some(x) if x.is_empty()) {
+                node.as_object_mut().unwrap().remove("children");
+            }
+        }
+        //World transforms at rest, the order of the skeleton doesn't matter
+        let mut world = rest.clone();
+        for (i, bone) in fk.bones.iter().enumerate() {
+            let mut t = rest[i + 2];
+            let mut parent = bone.parent;
+            while let Some(p) = parent {
+                t = rest[p + 2] * t;
+                parent = fk.bones[p].parent;
+            }
+            world[i + 2] = t;
+        }
+        let inverse_bind: Vec<f32> = world
+            .iter()
+            .flat_map(|t| t.inverse().to_matrix().to_vec())
+            .collect();
+        let inverse_bind = builder.push(&inverse_bind, "MAT4", 16);
+
+        let frames = mot.frames.max(1);
+        let times: Vec<f32> = (0..frames).map(|f| f as f32 / fps).collect();
+        let mut samplers = vec![];
+        let mut channels = vec![];
+        let mut frame_times = None;
+        for (i, node) in nodes.iter().enumerate() {
+            let anim = match mot.anim(node["name"].as_str().unwrap_or_default()) {
+                Some(anim) => anim,
+                None => continue,
+            };
+            for (component, vec) in anim.components() {
+                let (input, output, path, interpolation) = match component {
+                    Component::Position => {
+                        let (input, output) = cubic_spline(vec, fps);
+                        let input = builder.push(&input, "SCALAR", 1);
+                        let output = builder.push(&output, "VEC3", 3);
+                        (input, output, "translation", "CUBICSPLINE")
+                    }
+                    Component::Rotation => {
+                        let mut prev = Quat::IDENTITY;
+                        let mut output = Vec::with_capacity(frames as usize * 4);
+                        for f in 0..frames {
+                            let mut q = Quat::from_euler(sample(vec, f as f32));
+                            //Stay on the same hemisphere so nothing spins the long way round
+                            if q.dot(prev) < 0. {
+                                q = Quat {
+                                    x: -q.x,
+                                    y: -q.y,
+                                    z: -q.z,
+                                    w: -q.w,
+                                };
+                            }
+                            output.extend_from_slice(&[q.x, q.y, q.z, q.w]);
+                            prev = q;
+                        }
+                        let input =
+                            *frame_times.get_or_insert_with(|| builder.push(&times, "SCALAR", 1));
+                        let output = builder.push(&output, "VEC4", 4);
+                        (input, output, "rotation", "LINEAR")
+                    }
+                    _ => continue,
+                };
+                samplers.push(json!({
+                    "input": input,
+                    "output": output,
+                    "interpolation": interpolation,
+                }));
+                channels.push(json!({
+                    "sampler": samplers.len() - 1,
+                    "target": { "node": i, "path": path },
+                }));
+            }
+        }
+
+        let joints: Vec<usize> = (0..nodes.len()).collect();
+        let json = json!({
+            "asset": { "version": "2.0", "generator": "mot_new" },
+            "scene": 0,
+            "scenes": [{ "nodes": [0] }],
+            "nodes": nodes,
+            "skins": [{
+                "joints": joints,
+                "skeleton": 0,
+                "inverseBindMatrices": inverse_bind,
+            }],
+            "animations": [{ "samplers": samplers, "channels": channels }],
+            "buffers": [{ "byteLength": builder.buffer.len() }],
+            "bufferViews": builder.views,
+            "accessors": builder.accessors,
+        });
+        Gltf {
+            json,
+            buffer: builder.buffer,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn base64() {
+        assert_eq!(base64_encode(b"Man"), "TWFu");
+        assert_eq!(base64_encode(b"Ma"), "TWE=");
+        assert_eq!(base64_encode(b"M"), "TQ==");
+        assert_eq!(base64_decode("TWE=").unwrap(), b"Ma");
+    }
+
+    #[test]
+    fn roundtrip() -> anyhow::Result<()> {
+        use crate::channel::ChannelPath;
+
+        let skeleton = Skeleton::test(&[
+            ("n_hara_cp", BoneType::Type3, None, &[[0., 1., 0.]]),
+            ("kl_kubi", BoneType::Rotation, Some(0), &[[0., 0.5, 0.]]),
+            ("kl_te_l", BoneType::Type1, Some(0), &[[0.5, 0., 0.]]),
+        ]);
+        let line = |a: f32, b: f32| {
+            let samples: Vec<f32> = (0..11).map(|i| a + (b - a) * i as f32 / 10.).collect();
+            FrameData::fit(0, &samples, 1e-5)
+        };
+        let mut anims = BTreeMap::new();
+        anims.insert(
+            Bone("n_hara_cp".into()),
+            Some(BoneAnim::PositionRotation {
+                position: (line(0., 1.), FrameData::Pose(1.), FrameData::None),
+                rotation: (FrameData::Pose(0.), line(0., 1.), FrameData::Pose(0.)),
+            }),
+        );
+        anims.insert(
+            Bone("kl_kubi".into()),
+            Some(BoneAnim::Rotation((
+                line(0.5, -0.5),
+                FrameData::Pose(0.),
+                FrameData::Pose(0.),
+            ))),
+        );
+        let none = || (FrameData::None, FrameData::None, FrameData::None);
+        anims.insert(Bone("kl_te_l".into()), Some(BoneAnim::Unk(none(), none())));
+        let mot = Motion { frames: 11, anims };
+
+        let glb = mot.to_gltf(&skeleton, 60., 1e-5).to_glb();
+        let gltf = Gltf::from_gltf(&Gltf::from_glb(&glb)?.to_gltf())?;
+        let back = Motion::from_gltf(&gltf, &skeleton, 0, 60., 1e-5)?;
+        assert_eq!(back.frames(), 11);
+        for path in &[
+            "n_hara_cp.position.x",
+            "n_hara_cp.rotation.y",
+            "kl_kubi.rotation.x",
+        ] {
+            let path = ChannelPath::parse(path)?;
+            for f in 0..11 {
+                let a = mot.get(&path).unwrap().evaluate(f as f32).unwrap();
+                let b = back.get(&path).unwrap().evaluate(f as f32).unwrap();
+                assert!((a - b).abs() < 1e-3, "{} at {}: {} != {}", path, f, a, b);
+            }
+        }
+        assert_eq!(back.anim("kl_te_l"), Some(&BoneAnim::Unk(none(), none())));
+        assert!(matches!(
+            Motion::from_gltf(&gltf, &skeleton, 0, 1e6, 1e-5),
+            Err(GltfError::TooLong)
+        ));
+        Ok(())
+    }
+
+    #[test]
+    fn accessor_out_of_buffer() {
+        let gltf = |count: u64| Gltf {
+            json: json!({
+                "accessors": [{
+                    "bufferView": 0,
+                    "type": "VEC3",
+                    "componentType": 5126,
+                    "count": count,
+                }],
+                "bufferViews": [{"buffer": 0, "byteStride": 16}],
+            }),
+            buffer: vec![0; 28],
+        };
+        assert_eq!(gltf(2).accessor(0).unwrap(), vec![0.; 6]);
+        assert!(matches!(
+            gltf(3).accessor(0),
+            Err(GltfError::BadAccessor(0))
+        ));
+        assert!(matches!(
+            gltf(u64::MAX).accessor(0),
+            Err(GltfError::BadAccessor(0))
+        ));
+    }
+
+    #[test]
+    fn hermite_becomes_cubic_spline() {
+        let mut x = FrameData::None;
+        x.insert_key(0, 0.);
+        x.insert_key(10, 1.);
+        let x = x.into_hermite();
+        let (times, output) = cubic_spline(&(x.clone(), FrameData::Pose(2.), FrameData::None), 60.);
+        assert_eq!(times, vec![0., 10. / 60.]);
+        //In tangent, value and out tangent of each key
+        assert_eq!(&output[3..6], &[0., 2., 0.]);
+        assert_eq!(&output[12..15], &[1., 2., 0.]);
+        assert_eq!(output[0], 0.);
+        assert!((output[6] - x.slope(0.).unwrap() * 60.).abs() < 1e-4);
+        assert_eq!(output[15], 0.);
+    }
+}
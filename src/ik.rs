@@ -0,0 +1,369 @@
+use super::*;
+use crate::blend::{fit, to_euler};
+use crate::math::{self, Quat, Transform, Vector3};
+use crate::skeleton::{Skeleton, SkeletonBone};
+use diva_db::bone::BoneType;
+
+///The solved joints of an IK bone at one frame
+#[derive(Clone, PartialEq, Debug, Default)]
+pub struct IkChain {
+    ///World transform of the IK bone's parent
+    pub parent: Transform,
+    ///World transforms of each joint, from the root of the chain to its end
+    pub joints: Vec<Transform>,
+}
+
+impl IkChain {
+    ///Each joint relative to the one before it, the first relative to the parent
+    pub fn local(&self) -> Vec<Transform> {
+        let mut prev = self.parent;
+        self.joints
+            .iter()
+            .map(|&joint| {
+                let local = prev.inverse() * joint;
+                prev = joint;
+                local
+            })
+            .collect()
+    }
+}
+
+///Names for the joints of IK chains once they are turned into plain FK bones
+const JOINTS: &[(&str, &[&str])] = &[
+    ("c_kata_l", &["j_kata_l_wj_cu", "j_ude_l_wj", "j_te_l_wj"]),
+    ("c_kata_r", &["j_kata_r_wj_cu", "j_ude_r_wj", "j_te_r_wj"]),
+    ("cl_momo_l", &["j_momo_l_wj", "j_sune_l_wj", "j_asi_l_wj"]),
+    ("cl_momo_r", &["j_momo_r_wj", "j_sune_r_wj", "j_asi_r_wj"]),
+    ("cl_kao", &["j_kao_wj", "j_kao_end_wj"]),
+    ("cl_mune", &["j_mune_wj", "j_mune_end_wj"]),
+];
+
+///The name of the `n`th joint of an IK bone's chain
+pub fn joint_name(bone: &str, n: usize) -> String {
+    JOINTS
+        .iter()
+        .find(|(x, _)| *x == bone)
+        .and_then(|(_, joints)| joints.get(n))
+        .map(|x| x.to_string())
+        .unwrap_or_else(|| format!("{}_ik{}", bone, n))
+}
+
+pub(crate) fn is_ik(mode: BoneType) -> bool {
+    matches!(mode, BoneType::Type4 | BoneType::Type5 | BoneType::Type6)
+}
+
+///Solves a chain starting at `root` towards `target`.
+///
+///`offsets` are the rest offsets of each following joint relative to the previous one. One
+///offset aims the chain at the target, two offsets bend it like the game's two bone IK, with the
+///bend plane taken from the rest pose turned by `root`. Targets out of reach are clamped.
+pub fn solve_chain(root: Transform, offsets: &[Vector3], target: Vector3) -> Vec<Transform> {
+    let origin = root.translation;
+    let to_target = math::sub(target, origin);
+    match offsets {
+        [end] => {
+            let aim = Quat::from_rotation_arc(root.rotation.rotate(*end), to_target);
+            let rotation = (aim * root.rotation).normalize();
+            let root = Transform::new(origin, rotation);
+            vec![root, root * Transform::new(*end, Quat::IDENTITY)]
+        }
+        [upper, lower] => {
+            let (l1, l2) = (math::length(*upper), math::length(*lower));
+            let dist = math::length(to_target)
+                .max((l1 - l2).abs() + 1e-6)
+                .min(l1 + l2 - 1e-6);
+            let dir = match math::length(to_target) {
+                x if x > 1e-6 => math::normalize(to_target),
+                _ => math::normalize(root.rotation.rotate(math::add(*upper, *lower))),
+            };
+
+            //Bend towards wherever the middle joint points at rest
+            let rest_end = math::add(*upper, *lower);
+            let along = math::scale(
+                rest_end,
+                math::dot(*upper, rest_end) / math::dot(rest_end, rest_end).max(1e-12),
+            );
+            let mut pole = root.rotation.rotate(math::sub(*upper, along));
+            if math::length(pole) < 1e-6 {
+                pole = root.rotation.rotate([0., 0., 1.]);
+            }
+            let perp = math::sub(pole, math::scale(dir, math::dot(pole, dir)));
+            let perp = if math::length(perp) < 1e-6 {
+                math::normalize(math::cross(dir, [1., 0., 0.]))
+            } else {
+                math::normalize(perp)
+            };
+
+            let cos = ((l1 * l1 + dist * dist - l2 * l2) / (2. * l1 * dist)).clamp(-1., 1.);
+            let sin = (1. - cos * cos).sqrt();
+            let middle = math::add(
+                origin,
+                math::add(math::scale(dir, cos * l1), math::scale(perp, sin * l1)),
+            );
+            let end = math::add(origin, math::scale(dir, dist));
+
+            let upper_rot =
+                Quat::from_rotation_arc(root.rotation.rotate(*upper), math::sub(middle, origin))
+                    * root.rotation;
+            let lower_rot =
+                Quat::from_rotation_arc(upper_rot.rotate(*lower), math::sub(end, middle))
+                    * upper_rot;
+            vec![
+                Transform::new(origin, upper_rot.normalize()),
+                Transform::new(middle, lower_rot.normalize()),
+                Transform::new(end, lower_rot.normalize()),
+            ]
+        }
+        _ => {
+            let mut joint = root;
+            let mut joints = vec![joint];
+            for offset in offsets {
+                joint = joint * Transform::new(*offset, Quat::IDENTITY);
+                joints.push(joint);
+            }
+            joints
+        }
+    }
+}
+
+///The rotation turning `from` onto `to`, where each is a direction and a roughly perpendicular
+///up vector. The directions are matched exactly, the up vectors as closely as possible.
+pub(crate) fn align(from: (Vector3, Vector3), to: (Vector3, Vector3)) -> Quat {
+    let dir = math::normalize(to.0);
+    let swing = Quat::from_rotation_arc(from.0, dir);
+    let flat = |v: Vector3| math::sub(v, math::scale(dir, math::dot(v, dir)));
+    let (a, b) = (flat(swing.rotate(from.1)), flat(to.1));
+    if math::length(a) < 1e-6 || math::length(b) < 1e-6 {
+        return swing;
+    }
+    let angle = math::dot(math::cross(a, b), dir).atan2(math::dot(a, b));
+    (Quat::from_axis_angle(dir, angle) * swing).normalize()
+}
+
+///The world rotation to give the root of a chain so [`solve_chain`] bends it through `joints`
+fn chain_rotation(offsets: &[Vector3], joints: &[Transform]) -> Quat {
+    match (offsets, joints) {
+        ([upper, lower], [root, middle, end]) => {
+            let rest_end = math::add(*upper, *lower);
+            let along = math::scale(
+                rest_end,
+                math::dot(*upper, rest_end) / math::dot(rest_end, rest_end).max(1e-12),
+            );
+            let mut pole = math::sub(*upper, along);
+            if math::length(pole) < 1e-6 {
+                pole = [0., 0., 1.];
+            }
+            let to_end = math::sub(end.translation, root.translation);
+            let to_middle = math::sub(middle.translation, root.translation);
+            align((rest_end, pole), (to_end, to_middle))
+        }
+        _ => joints[0].rotation,
+    }
+}
+
+impl<'a> Skeleton<'a> {
+    ///Replaces every IK bone with a plain chain of joints named by [`joint_name`].
+    ///
+    ///Children of an IK bone hang off the last joint of its chain.
+    pub fn without_ik(&self) -> Skeleton<'static> {
+        let mut bones: Vec<SkeletonBone<'static>> = Vec::with_capacity(self.bones.len());
+        //The first and last new bone of every original bone
+        let mut spans = Vec::with_capacity(self.bones.len());
+        for bone in &self.bones {
+            let first = bones.len();
+            if !is_ik(bone.mode) || bone.positions.len() < 2 {
+                bones.push(SkeletonBone {
+                    name: Cow::Owned(bone.name.to_string()),
+                    mode: bone.mode,
+                    parent: None,
+                    positions: vec![bone.rest()],
+                });
+            } else {
+                for (n, &offset) in bone.positions.iter().enumerate() {
+                    let (mode, parent) = match n {
+                        0 => (BoneType::Type3, None),
+                        _ => (BoneType::Rotation, Some(bones.len() - 1)),
+                    };
+                    bones.push(SkeletonBone {
+                        name: Cow::Owned(joint_name(&bone.name, n)),
+                        mode,
+                        parent,
+                        positions: vec![offset],
+                    });
+                }
+            }
+            spans.push((first, bones.len() - 1));
+        }
+        //Children of an IK bone now hang off the end of its chain
+        for (bone, &(first, _)) in self.bones.iter().zip(&spans) {
+            bones[first].parent = bone.parent.map(|p| spans[p].1);
+        }
+        Skeleton { bones }
+    }
+}
+
+impl<'a> Motion<'a> {
+    ///Solves every IK bone of `skeleton` at `frame`
+    pub fn solve_ik(&self, skeleton: &Skeleton, frame: f32) -> BTreeMap<String, IkChain> {
+        self.world_pose(skeleton, frame).chains
+    }
+
+    ///Bakes the IK bones into plain rotations on the joints of [`Skeleton::without_ik`], so the
+    ///motion plays back the same on a skeleton without IK
+    pub fn bake_ik(&self, skeleton: &Skeleton, tolerance: f32) -> Motion<'a> {
+        let frames = self.frames.max(1);
+        let poses: Vec<_> = (0..frames)
+            .map(|f| self.solve_ik(skeleton, f as f32))
+            .collect();
+        let mut mot = self.clone();
+        for bone in skeleton.bones.iter().filter(|b| is_ik(b.mode)) {
+            let chains: Vec<&IkChain> = match poses.iter().map(|p| p.get(&bone.name[..])).collect()
+            {
+                Some(chains) => chains,
+                None => continue,
+            };
+            mot.anims.retain(|b, _| b[..] != bone.name[..]);
+            let locals: Vec<Vec<Transform>> = chains.iter().map(|c| c.local()).collect();
+            let joints = locals.first().map(Vec::len).unwrap_or_default();
+            for n in 0..joints {
+                let rotations: Vec<Quat> = locals.iter().map(|l| l[n].rotation).collect();
+                let rotation = fit(0, &to_euler(&rotations), tolerance);
+                let anim = if n == 0 {
+                    let positions: Vec<Vector3> = locals.iter().map(|l| l[n].translation).collect();
+                    BoneAnim::PositionRotation {
+                        position: fit(0, &positions, tolerance),
+                        rotation,
+                    }
+                } else {
+                    BoneAnim::Rotation(rotation)
+                };
+                mot.anims
+                    .insert(Bone(Cow::Owned(joint_name(&bone.name, n))), Some(anim));
+            }
+        }
+        mot
+    }
+
+    ///The reverse of [`Motion::bake_ik`]: turns the joint rotations of a motion made for
+    ///[`Skeleton::without_ik`] back into the IK bones of `skeleton`.
+    ///
+    ///Arms keep the plane they bend in through their rotation. Legs have no rotation, so their
+    ///knees bend the way the rest pose does.
+    pub fn to_ik(&self, skeleton: &Skeleton, tolerance: f32) -> Motion<'a> {
+        let fk = skeleton.without_ik();
+        let frames = self.frames.max(1);
+        let poses: Vec<_> = (0..frames)
+            .map(|f| self.world_pose(&fk, f as f32))
+            .collect();
+        let mut mot = self.clone();
+        for bone in skeleton.bones.iter().filter(|b| is_ik(b.mode)) {
+            let names: Vec<String> = (0..bone.positions.len())
+                .map(|n| joint_name(&bone.name, n))
+                .collect();
+            let parent = match fk.get(&names[0]) {
+                Some(joint) => joint.parent.map(|p| &fk.bones[p].name[..]),
+                None => continue,
+            };
+            let mut roots = Vec::with_capacity(frames as usize);
+            let mut targets = Vec::with_capacity(frames as usize);
+            let mut rotations = Vec::with_capacity(frames as usize);
+            for pose in &poses {
+                let parent = match parent {
+                    Some(name) => pose.get(name).unwrap_or_default(),
+                    None => pose.root,
+                };
+                let joints: Vec<Transform> = names
+                    .iter()
+                    .map(|n| pose.get(n).unwrap_or_default())
+                    .collect();
+                let to_parent = parent.inverse();
+                let rotation = chain_rotation(&bone.positions[1..], &joints);
+                roots.push(to_parent.apply(joints[0].translation));
+                targets.push(to_parent.apply(joints[joints.len() - 1].translation));
+                rotations.push((to_parent.rotation * rotation).normalize());
+            }
+            let target = fit(0, &targets, tolerance);
+            let rotation = || fit(0, &to_euler(&rotations), tolerance);
+            let anim = match bone.mode {
+                BoneType::Type4 => BoneAnim::RotationIk {
+                    target,
+                    rotation: rotation(),
+                },
+                BoneType::Type5 => BoneAnim::ArmIk {
+                    target,
+                    rotation: rotation(),
+                },
+                _ => BoneAnim::LegIk {
+                    position: fit(0, &roots, tolerance),
+                    target,
+                },
+            };
+            mot.anims
+                .retain(|b, _| !names.iter().any(|n| b[..] == n[..]));
+            mot.anims
+                .insert(Bone(Cow::Owned(bone.name.to_string())), Some(anim));
+        }
+        mot
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::math::{length, sub};
+
+    #[test]
+    fn two_bone_reaches_target() {
+        let offsets = [[0., -1., 0.], [0., -1., 0.]];
+        let target = [0.5, -1.2, 0.3];
+        let joints = solve_chain(Transform::IDENTITY, &offsets, target);
+        assert!(length(sub(joints[2].translation, target)) < 1e-4);
+        //Bone lengths are kept
+        let upper = length(sub(joints[1].translation, joints[0].translation));
+        let lower = length(sub(joints[2].translation, joints[1].translation));
+        assert!((upper - 1.).abs() < 1e-4 && (lower - 1.).abs() < 1e-4);
+        //The joints' rotations carry the rest offsets onto the solved positions
+        let middle = joints[0].apply(offsets[0]);
+        assert!(length(sub(middle, joints[1].translation)) < 1e-4);
+    }
+
+    #[test]
+    fn bake_roundtrip() {
+        let v = |x, y, z| (FrameData::Pose(x), FrameData::Pose(y), FrameData::Pose(z));
+        let skeleton = Skeleton::test(&[
+            ("n_hara_cp", BoneType::Type3, None, &[[0., 1., 0.]]),
+            (
+                "c_kata_l",
+                BoneType::Type5,
+                Some(0),
+                &[[0.2, 0.3, 0.], [0.3, 0., -0.05], [0.3, 0., 0.05]],
+            ),
+        ]);
+        let mut anims = BTreeMap::new();
+        anims.insert(
+            Bone("c_kata_l".into()),
+            Some(BoneAnim::ArmIk {
+                target: v(0.6, -0.1, 0.2),
+                rotation: v(0.4, 0., 0.),
+            }),
+        );
+        let mot = Motion { frames: 2, anims };
+        let chain = &mot.solve_ik(&skeleton, 0.)["c_kata_l"];
+
+        let fk = mot.bake_ik(&skeleton, 1e-5);
+        assert!(fk.anim("j_ude_l_wj").is_some() && fk.anim("c_kata_l").is_none());
+        let ik = fk.to_ik(&skeleton, 1e-5);
+        assert!(ik.anim("j_ude_l_wj").is_none());
+        let back = &ik.solve_ik(&skeleton, 0.)["c_kata_l"];
+        for (a, b) in chain.joints.iter().zip(&back.joints) {
+            assert!(length(sub(a.translation, b.translation)) < 1e-3);
+        }
+    }
+
+    #[test]
+    fn unreachable_target_is_clamped() {
+        let offsets = [[1., 0., 0.], [1., 0., 0.]];
+        let joints = solve_chain(Transform::IDENTITY, &offsets, [5., 0., 0.]);
+        assert!(length(sub(joints[2].translation, [2., 0., 0.])) < 1e-3);
+    }
+}
@@ -5,14 +5,42 @@ use thiserror::Error;
 use std::collections::{BTreeMap, VecDeque};
 use std::borrow::Cow;
 
+pub mod a3da;
+pub mod channel;
+pub mod contact;
+pub mod blend;
+pub mod bvh;
+pub mod csv;
+pub mod curve;
+#[cfg(feature = "gltf")]
+pub mod gltf;
+pub mod ik;
+pub mod mask;
+pub mod math;
+mod mirror;
+pub mod pose;
+pub mod retarget;
+pub mod retime;
+pub mod root;
+mod timeline;
 mod ordering;
 #[cfg(feature = "pyo3")]
 pub mod python_ffi;
 mod read;
+#[cfg(feature = "serde")]
+mod serialize;
 mod write;
 pub mod qualify;
+pub mod skeleton;
+pub mod text;
+pub mod usd;
+#[cfg(feature = "vmd")]
+pub mod vmd;
+#[cfg(feature = "xml")]
+pub mod xml;
 
 #[derive(Clone, PartialEq, PartialOrd, Debug, Default)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct RawMotion {
     sets: Vec<FrameData>,
     bones: Vec<u16>,
@@ -20,35 +48,65 @@ pub struct RawMotion {
 }
 
 #[derive(Clone, PartialEq, PartialOrd, Debug, Default)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct Motion<'a> {
     frames: u16,
     pub anims: BTreeMap<Bone<'a>, Option<BoneAnim>>,
 }
 
 #[derive(Debug, PartialEq, Eq, Default, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "serde", serde(transparent))]
 pub struct Bone<'a>(Cow<'a, str>);
 
 type Vec3 = (FrameData, FrameData, FrameData);
 
 #[derive(Clone, PartialEq, PartialOrd, Debug)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "serde", serde(rename_all = "snake_case"))]
 pub enum BoneAnim {
     ///Corresponds to Type 0
-    Rotation(Vec3),
+    Rotation(#[cfg_attr(feature = "serde", serde(with = "serialize::vec3"))] Vec3),
     ///Corresponds to Type 1
-    Unk(Vec3, Vec3),
+    Unk(
+        #[cfg_attr(feature = "serde", serde(with = "serialize::vec3"))] Vec3,
+        #[cfg_attr(feature = "serde", serde(with = "serialize::vec3"))] Vec3,
+    ),
     ///Corresponds to Type 2
-    Position(Vec3),
+    Position(#[cfg_attr(feature = "serde", serde(with = "serialize::vec3"))] Vec3),
     ///Corresponds to Type 3
-    PositionRotation { position: Vec3, rotation: Vec3 },
+    PositionRotation {
+        #[cfg_attr(feature = "serde", serde(with = "serialize::vec3"))]
+        position: Vec3,
+        #[cfg_attr(feature = "serde", serde(with = "serialize::vec3"))]
+        rotation: Vec3,
+    },
     ///Corresponds to Type 4
-    RotationIk { target: Vec3, rotation: Vec3 },
+    RotationIk {
+        #[cfg_attr(feature = "serde", serde(with = "serialize::vec3"))]
+        target: Vec3,
+        #[cfg_attr(feature = "serde", serde(with = "serialize::vec3"))]
+        rotation: Vec3,
+    },
     ///Corresponds to Type 5
-    ArmIk { target: Vec3, rotation: Vec3 },
+    ArmIk {
+        #[cfg_attr(feature = "serde", serde(with = "serialize::vec3"))]
+        target: Vec3,
+        #[cfg_attr(feature = "serde", serde(with = "serialize::vec3"))]
+        rotation: Vec3,
+    },
     ///Corresponds to Type 6
-    LegIk { position: Vec3, target: Vec3 },
+    LegIk {
+        #[cfg_attr(feature = "serde", serde(with = "serialize::vec3"))]
+        position: Vec3,
+        #[cfg_attr(feature = "serde", serde(with = "serialize::vec3"))]
+        target: Vec3,
+    },
 }
 
 #[derive(Clone, PartialEq, PartialOrd, Debug)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "serde", serde(rename_all = "snake_case"))]
 pub enum FrameData {
     None,
     Pose(f32),
@@ -59,8 +117,17 @@ pub enum FrameData {
 type Hermite = f32;
 
 #[derive(Copy, Clone, PartialEq, PartialOrd, Debug, Default)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct Keyframe<I = ()> {
     pub frame: u16,
     pub value: f32,
+    #[cfg_attr(
+        feature = "serde",
+        serde(
+            rename = "tangent",
+            default,
+            skip_serializing_if = "serialize::is_unit"
+        )
+    )]
     pub interpolation: I,
 }
@@ -0,0 +1,275 @@
+use super::*;
+use crate::skeleton::Skeleton;
+
+use std::collections::BTreeSet;
+
+///Named regions of the body, taken from the subtrees of a skeleton
+#[derive(Copy, Clone, PartialEq, Eq, PartialOrd, Ord, Hash, Debug)]
+pub enum BoneGroup {
+    ///`gblctr`, `kg_ya_ex` and every bone outside of the upper and lower body, like the hip
+    ///control bones
+    Root,
+    ///Everything under `face_root`
+    Face,
+    ///Eyeballs, eyelids and highlights
+    Eyes,
+    ///Lips, jaw and teeth
+    Mouth,
+    ///The left hand and fingers
+    LeftHand,
+    ///The right hand and fingers
+    RightHand,
+    ///Everything under the chest and shoulders, including the neck, head, face and arms
+    UpperBody,
+    ///Everything under the waist and thighs
+    LowerBody,
+}
+
+const ROOT_BONES: &[&str] = &["gblctr", "kg_ya_ex"];
+//The bones whose subtrees make up each group
+const FACE: &[&str] = &["face_root"];
+const LEFT_HAND: &[&str] = &["kl_te_l_wj"];
+const RIGHT_HAND: &[&str] = &["kl_te_r_wj"];
+const UPPER_BODY: &[&str] = &[
+    "e_mune_cp",
+    "cl_mune",
+    "e_ude_l_cp",
+    "c_kata_l",
+    "e_ude_r_cp",
+    "c_kata_r",
+];
+const LOWER_BODY: &[&str] = &[
+    "kl_kosi_y",
+    "e_sune_l_cp",
+    "cl_momo_l",
+    "e_sune_r_cp",
+    "cl_momo_r",
+];
+//Name parts that split the face
+const EYES: &[&str] = &["eye", "mabu", "eyelid", "highlight"];
+const MOUTH: &[&str] = &["kuti", "ago", "tooth", "ha"];
+
+impl BoneGroup {
+    pub const ALL: [BoneGroup; 8] = [
+        BoneGroup::Root,
+        BoneGroup::Face,
+        BoneGroup::Eyes,
+        BoneGroup::Mouth,
+        BoneGroup::LeftHand,
+        BoneGroup::RightHand,
+        BoneGroup::UpperBody,
+        BoneGroup::LowerBody,
+    ];
+
+    ///The bones of `skeleton` in the group
+    pub fn mask(self, skeleton: &Skeleton) -> BoneMask {
+        let subtrees = |roots: &[&str]| {
+            roots.iter().fold(BoneMask::new(), |mask, root| {
+                mask.union(&skeleton.subtree(root))
+            })
+        };
+        let named = |tokens: &[&str]| -> BoneMask {
+            subtrees(FACE)
+                .iter()
+                .filter(|name| name.split('_').any(|p| tokens.contains(&p)))
+                .collect()
+        };
+        match self {
+            BoneGroup::Root => {
+                let body = subtrees(UPPER_BODY).union(&subtrees(LOWER_BODY));
+                let all: BoneMask = skeleton.bones.iter().map(|b| &b.name[..]).collect();
+                let roots: BoneMask = ROOT_BONES.iter().collect();
+                all.difference(&body).union(&roots)
+            }
+            BoneGroup::Face => subtrees(FACE),
+            BoneGroup::Eyes => named(EYES),
+            BoneGroup::Mouth => named(MOUTH),
+            BoneGroup::LeftHand => subtrees(LEFT_HAND),
+            BoneGroup::RightHand => subtrees(RIGHT_HAND),
+            BoneGroup::UpperBody => subtrees(UPPER_BODY),
+            BoneGroup::LowerBody => subtrees(LOWER_BODY),
+        }
+    }
+}
+
+///A set of bones to pick from a motion
+#[derive(Clone, PartialEq, Eq, Debug, Default)]
+pub struct BoneMask {
+    bones: BTreeSet<String>,
+}
+
+impl BoneMask {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    ///Every known motion bone
+    pub fn all() -> Self {
+        ordering::names()
+            .chain(ROOT_BONES.iter().copied())
+            .collect()
+    }
+
+    ///The bones of `skeleton` in `group`
+    pub fn group(skeleton: &Skeleton, group: BoneGroup) -> Self {
+        group.mask(skeleton)
+    }
+
+    pub fn contains(&self, name: &str) -> bool {
+        self.bones.contains(name)
+    }
+
+    pub fn insert(&mut self, name: &str) {
+        self.bones.insert(name.to_string());
+    }
+
+    pub fn remove(&mut self, name: &str) {
+        self.bones.remove(name);
+    }
+
+    pub fn union(&self, other: &BoneMask) -> Self {
+        Self {
+            bones: self.bones.union(&other.bones).cloned().collect(),
+        }
+    }
+
+    pub fn difference(&self, other: &BoneMask) -> Self {
+        Self {
+            bones: self.bones.difference(&other.bones).cloned().collect(),
+        }
+    }
+
+    ///Every known motion bone not in this mask
+    pub fn invert(&self) -> Self {
+        Self::all().difference(self)
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = &str> {
+        self.bones.iter().map(|x| &x[..])
+    }
+}
+
+impl<S: AsRef<str>> core::iter::FromIterator<S> for BoneMask {
+    fn from_iter<T: IntoIterator<Item = S>>(iter: T) -> Self {
+        Self {
+            bones: iter.into_iter().map(|x| x.as_ref().to_string()).collect(),
+        }
+    }
+}
+
+impl<'a> Motion<'a> {
+    ///Keeps only the bones in `mask`
+    pub fn filter(&self, mask: &BoneMask) -> Self {
+        let anims = self
+            .anims
+            .iter()
+            .filter(|(bone, _)| mask.contains(bone))
+            .map(|(bone, anim)| (bone.clone(), anim.clone()))
+            .collect();
+        Self {
+            frames: self.frames,
+            anims,
+        }
+    }
+
+    ///Takes the bones in `mask` from `other` and every other bone from `self`
+    pub fn merge(&self, other: &Motion<'a>, mask: &BoneMask) -> Self {
+        let mut anims: BTreeMap<_, _> = self
+            .anims
+            .iter()
+            .filter(|(bone, _)| !mask.contains(bone))
+            .map(|(bone, anim)| (bone.clone(), anim.clone()))
+            .collect();
+        anims.extend(other.filter(mask).anims);
+        Self {
+            frames: self.frames.max(other.frames),
+            anims,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use diva_db::bone::BoneType;
+
+    fn skeleton() -> Skeleton<'static> {
+        let names: &[(&'static str, Option<usize>)] = &[
+            ("n_hara_cp", None),
+            ("kl_hara_etc", Some(0)),
+            ("cl_mune", Some(1)),
+            ("kl_kubi", Some(2)),
+            ("face_root", Some(3)),
+            ("kl_eye_l_wj", Some(4)),
+            ("tl_kuti_d_l_wj", Some(4)),
+            ("tl_mayu_l_wj", Some(4)),
+            ("c_kata_l", Some(2)),
+            ("kl_te_l_wj", Some(8)),
+            ("nl_hito_b_l_wj", Some(9)),
+            ("c_kata_r", Some(2)),
+            ("kl_te_r_wj", Some(11)),
+            ("n_oya_r_ex", Some(12)),
+            ("kl_kosi_y", Some(1)),
+            ("n_hara_b_wj_ex", Some(14)),
+            ("cl_momo_l", Some(14)),
+            ("kl_asi_l_wj_co", Some(16)),
+            ("n_custom_ex", Some(1)),
+        ];
+        let bones: Vec<_> = names
+            .iter()
+            .map(|&(name, parent)| (name, BoneType::Rotation, parent, &[[0.; 3]][..]))
+            .collect();
+        Skeleton::test(&bones)
+    }
+
+    #[test]
+    fn groups() {
+        use BoneGroup::*;
+        let skeleton = skeleton();
+        let group = |group: BoneGroup| BoneMask::group(&skeleton, group);
+        assert!(group(LeftHand).contains("nl_hito_b_l_wj"));
+        assert!(group(LeftHand).contains("kl_te_l_wj"));
+        assert!(!group(LeftHand).contains("kl_te_r_wj"));
+        assert!(group(RightHand).contains("n_oya_r_ex"));
+        assert!(group(Eyes).contains("kl_eye_l_wj"));
+        assert!(group(Mouth).contains("tl_kuti_d_l_wj"));
+        assert!(!group(Eyes).contains("tl_kuti_d_l_wj"));
+        assert!(group(Face).contains("tl_mayu_l_wj"));
+        assert!(!group(Face).contains("kl_kubi"));
+        assert!(group(Root).contains("gblctr"));
+        assert!(group(Root).contains("kl_hara_etc"));
+        assert!(group(Root).contains("n_custom_ex"));
+        assert!(group(LowerBody).contains("kl_asi_l_wj_co"));
+        //Helper bones go wherever they hang in the hierarchy
+        assert!(group(LowerBody).contains("n_hara_b_wj_ex"));
+        assert!(!group(UpperBody).contains("n_hara_b_wj_ex"));
+        assert!(group(UpperBody).contains("c_kata_l"));
+        assert!(!group(UpperBody).contains("cl_momo_l"));
+        assert!(!group(UpperBody).contains("n_hara_cp"));
+    }
+
+    #[test]
+    fn merge_hands() {
+        let anim = |x| {
+            Some(BoneAnim::Rotation((
+                FrameData::Pose(x),
+                FrameData::None,
+                FrameData::None,
+            )))
+        };
+        let motion = |x| {
+            let mut anims = BTreeMap::new();
+            anims.insert(Bone("kl_te_l_wj".into()), anim(x));
+            anims.insert(Bone("kl_kubi".into()), anim(x));
+            Motion { frames: 1, anims }
+        };
+        let skeleton = skeleton();
+        let hands = BoneMask::group(&skeleton, BoneGroup::LeftHand)
+            .union(&BoneMask::group(&skeleton, BoneGroup::RightHand));
+        let mot = motion(0.).merge(&motion(1.), &hands);
+        assert_eq!(mot.anims.len(), 2);
+        assert_eq!(mot.anim("kl_te_l_wj"), anim(1.).as_ref());
+        assert_eq!(mot.anim("kl_kubi"), anim(0.).as_ref());
+        assert_eq!(mot.filter(&hands).anims.len(), 1);
+    }
+}
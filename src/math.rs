@@ -0,0 +1,315 @@
+//!Minimal vector and quaternion math for evaluating poses.
+//!
+//!Euler angles are in radians and follow the game's convention: `x` is applied first, then `y`,
+//!then `z`, i.e. `R = Rz * Ry * Rx`.
+
+use core::f32::consts::PI;
+use core::ops::Mul;
+
+pub type Vector3 = [f32; 3];
+
+pub fn add(a: Vector3, b: Vector3) -> Vector3 {
+    [a[0] + b[0], a[1] + b[1], a[2] + b[2]]
+}
+
+pub fn sub(a: Vector3, b: Vector3) -> Vector3 {
+    [a[0] - b[0], a[1] - b[1], a[2] - b[2]]
+}
+
+pub fn scale(a: Vector3, s: f32) -> Vector3 {
+    [a[0] * s, a[1] * s, a[2] * s]
+}
+
+pub fn lerp(a: Vector3, b: Vector3, t: f32) -> Vector3 {
+    add(a, scale(sub(b, a), t))
+}
+
+pub fn dot(a: Vector3, b: Vector3) -> f32 {
+    a[0] * b[0] + a[1] * b[1] + a[2] * b[2]
+}
+
+pub fn cross(a: Vector3, b: Vector3) -> Vector3 {
+    [
+        a[1] * b[2] - a[2] * b[1],
+        a[2] * b[0] - a[0] * b[2],
+        a[0] * b[1] - a[1] * b[0],
+    ]
+}
+
+pub fn length(a: Vector3) -> f32 {
+    dot(a, a).sqrt()
+}
+
+pub fn normalize(a: Vector3) -> Vector3 {
+    let len = length(a);
+    if len == 0. {
+        a
+    } else {
+        scale(a, 1. / len)
+    }
+}
+
+#[derive(Copy, Clone, PartialEq, PartialOrd, Debug)]
+pub struct Quat {
+    pub x: f32,
+    pub y: f32,
+    pub z: f32,
+    pub w: f32,
+}
+
+impl Default for Quat {
+    fn default() -> Self {
+        Self::IDENTITY
+    }
+}
+
+impl Quat {
+    pub const IDENTITY: Quat = Quat {
+        x: 0.,
+        y: 0.,
+        z: 0.,
+        w: 1.,
+    };
+
+    pub fn from_axis_angle(axis: Vector3, angle: f32) -> Self {
+        let [x, y, z] = scale(normalize(axis), (angle / 2.).sin());
+        Self {
+            x,
+            y,
+            z,
+            w: (angle / 2.).cos(),
+        }
+    }
+
+    pub fn from_euler([x, y, z]: Vector3) -> Self {
+        Self::from_axis_angle([0., 0., 1.], z)
+            * Self::from_axis_angle([0., 1., 0.], y)
+            * Self::from_axis_angle([1., 0., 0.], x)
+    }
+
+    pub fn to_euler(self) -> Vector3 {
+        let Quat { x, y, z, w } = self;
+        let r00 = 1. - 2. * (y * y + z * z);
+        let r10 = 2. * (x * y + w * z);
+        let r20 = 2. * (x * z - w * y);
+        let r21 = 2. * (y * z + w * x);
+        let r22 = 1. - 2. * (x * x + y * y);
+        [
+            r21.atan2(r22),
+            (-r20).clamp(-1., 1.).asin(),
+            r10.atan2(r00),
+        ]
+    }
+
+    ///The shortest rotation turning the direction `from` into `to`
+    pub fn from_rotation_arc(from: Vector3, to: Vector3) -> Self {
+        let (from, to) = (normalize(from), normalize(to));
+        let d = dot(from, to);
+        if d < -1. + 1e-6 {
+            //Opposite directions, rotate half a turn around any perpendicular axis
+            let mut axis = cross([1., 0., 0.], from);
+            if length(axis) < 1e-6 {
+                axis = cross([0., 1., 0.], from);
+            }
+            return Self::from_axis_angle(axis, PI);
+        }
+        let [x, y, z] = cross(from, to);
+        Self { x, y, z, w: 1. + d }.normalize()
+    }
+
+    pub fn dot(self, other: Quat) -> f32 {
+        self.x * other.x + self.y * other.y + self.z * other.z + self.w * other.w
+    }
+
+    pub fn normalize(self) -> Self {
+        let len = self.dot(self).sqrt();
+        if len == 0. {
+            return Self::IDENTITY;
+        }
+        Self {
+            x: self.x / len,
+            y: self.y / len,
+            z: self.z / len,
+            w: self.w / len,
+        }
+    }
+
+    pub fn conjugate(self) -> Self {
+        Self {
+            x: -self.x,
+            y: -self.y,
+            z: -self.z,
+            w: self.w,
+        }
+    }
+
+    pub fn rotate(self, v: Vector3) -> Vector3 {
+        let q = [self.x, self.y, self.z];
+        let t = scale(cross(q, v), 2.);
+        add(add(v, scale(t, self.w)), cross(q, t))
+    }
+
+    ///Spherical interpolation along the shortest path
+    pub fn slerp(self, other: Quat, t: f32) -> Self {
+        let mut d = self.dot(other);
+        let other = if d < 0. {
+            d = -d;
+            Quat {
+                x: -other.x,
+                y: -other.y,
+                z: -other.z,
+                w: -other.w,
+            }
+        } else {
+            other
+        };
+        let (a, b) = if d > 0.9995 {
+            (1. - t, t)
+        } else {
+            let theta = d.acos();
+            let sin = theta.sin();
+            (((1. - t) * theta).sin() / sin, (t * theta).sin() / sin)
+        };
+        Self {
+            x: self.x * a + other.x * b,
+            y: self.y * a + other.y * b,
+            z: self.z * a + other.z * b,
+            w: self.w * a + other.w * b,
+        }
+        .normalize()
+    }
+}
+
+impl Mul for Quat {
+    type Output = Quat;
+
+    fn mul(self, r: Quat) -> Quat {
+        let l = self;
+        Quat {
+            w: l.w * r.w - l.x * r.x - l.y * r.y - l.z * r.z,
+            x: l.w * r.x + l.x * r.w + l.y * r.z - l.z * r.y,
+            y: l.w * r.y - l.x * r.z + l.y * r.w + l.z * r.x,
+            z: l.w * r.z + l.x * r.y - l.y * r.x + l.z * r.w,
+        }
+    }
+}
+
+///A rigid transform, rotating before translating
+#[derive(Copy, Clone, PartialEq, PartialOrd, Debug, Default)]
+pub struct Transform {
+    pub translation: Vector3,
+    pub rotation: Quat,
+}
+
+impl Transform {
+    pub const IDENTITY: Transform = Transform {
+        translation: [0.; 3],
+        rotation: Quat::IDENTITY,
+    };
+
+    pub fn new(translation: Vector3, rotation: Quat) -> Self {
+        Self {
+            translation,
+            rotation,
+        }
+    }
+
+    pub fn apply(self, point: Vector3) -> Vector3 {
+        add(self.translation, self.rotation.rotate(point))
+    }
+
+    pub fn inverse(self) -> Self {
+        let rotation = self.rotation.conjugate();
+        Self {
+            translation: rotation.rotate(scale(self.translation, -1.)),
+            rotation,
+        }
+    }
+
+    ///Column major matrix of the transform, which is also the row major layout of USD's row
+    ///vector matrices
+    pub fn to_matrix(self) -> [f32; 16] {
+        let Quat { x, y, z, w } = self.rotation;
+        let [tx, ty, tz] = self.translation;
+        [
+            1. - 2. * (y * y + z * z),
+            2. * (x * y + w * z),
+            2. * (x * z - w * y),
+            0.,
+            2. * (x * y - w * z),
+            1. - 2. * (x * x + z * z),
+            2. * (y * z + w * x),
+            0.,
+            2. * (x * z + w * y),
+            2. * (y * z - w * x),
+            1. - 2. * (x * x + y * y),
+            0.,
+            tx,
+            ty,
+            tz,
+            1.,
+        ]
+    }
+}
+
+impl Mul for Transform {
+    type Output = Transform;
+
+    ///Applies `child` first, then `self`
+    fn mul(self, child: Transform) -> Transform {
+        Transform {
+            translation: self.apply(child.translation),
+            rotation: (self.rotation * child.rotation).normalize(),
+        }
+    }
+}
+
+///Picks the Euler triple equivalent to `euler` that lies closest to `prev`, so baked curves
+///don't jump by full turns between frames
+pub fn unwrap_euler(euler: Vector3, prev: Vector3) -> Vector3 {
+    let wrap = |a: f32, p: f32| a + 2. * PI * ((p - a) / (2. * PI)).round();
+    let [x, y, z] = euler;
+    let candidates = [[x, y, z], [x + PI, PI - y, z + PI]];
+    let mut best = euler;
+    let mut best_dist = f32::INFINITY;
+    for c in candidates.iter() {
+        let c = [wrap(c[0], prev[0]), wrap(c[1], prev[1]), wrap(c[2], prev[2])];
+        let d = sub(c, prev);
+        let dist = dot(d, d);
+        if dist < best_dist {
+            best = c;
+            best_dist = dist;
+        }
+    }
+    best
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn close(a: Vector3, b: Vector3) -> bool {
+        length(sub(a, b)) < 1e-4
+    }
+
+    #[test]
+    fn euler_roundtrip() {
+        let e = [0.3, -0.7, 1.2];
+        assert!(close(Quat::from_euler(e).to_euler(), e));
+        //X is applied before Z
+        let q = Quat::from_euler([PI / 2., 0., PI / 2.]);
+        assert!(close(q.rotate([0., 1., 0.]), [0., 0., 1.]));
+    }
+
+    #[test]
+    fn unwrap_follows_previous() {
+        let e = unwrap_euler([-PI + 0.1, 0., 0.], [PI - 0.1, 0., 0.]);
+        assert!(close(e, [PI + 0.1, 0., 0.]));
+    }
+
+    #[test]
+    fn rotation_arc() {
+        let q = Quat::from_rotation_arc([1., 0., 0.], [0., 1., 0.]);
+        assert!(close(q.rotate([1., 0., 0.]), [0., 1., 0.]));
+    }
+}
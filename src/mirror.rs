@@ -0,0 +1,241 @@
+use super::*;
+use crate::math;
+use crate::skeleton::{Skeleton, SkeletonBone};
+
+///Negates the X axis of a position or IK target
+fn flip_position((x, y, z): &Vec3) -> Vec3 {
+    (x.scaled(-1.), y.clone(), z.clone())
+}
+
+///Negates the Y and Z axes of an Euler rotation, which turns it the other way round the X axis
+fn flip_rotation((x, y, z): &Vec3) -> Vec3 {
+    (x.clone(), y.scaled(-1.), z.scaled(-1.))
+}
+
+///Whether the two sides of a pair share the same rest offsets instead of mirrored ones, meaning
+///one side is authored in a mirrored frame and the same curves already play back mirrored
+fn mirrored_frames(a: &SkeletonBone, b: &SkeletonBone) -> bool {
+    let same = |a: math::Vector3, b: math::Vector3| math::length(math::sub(a, b)) < 1e-4;
+    a.positions.len() == b.positions.len()
+        && a.positions.iter().any(|p| p[0].abs() > 1e-4)
+        && a.positions
+            .iter()
+            .zip(&b.positions)
+            .all(|(&a, &b)| same(a, b))
+}
+
+impl BoneAnim {
+    ///Reflects the animation across the body's YZ plane, for a bone whose axes line up with
+    ///its counterpart's mirror image.
+    ///
+    ///Positions, IK targets and the positions of `LegIk` have their X axis negated, rotations
+    ///including those of `RotationIk` and `ArmIk` chains their Y and Z axes. What the channels
+    ///of `Unk` hold isn't known, so they are copied unchanged.
+    pub fn mirrored(&self) -> Self {
+        match self {
+            BoneAnim::Rotation(rotation) => BoneAnim::Rotation(flip_rotation(rotation)),
+            BoneAnim::Unk(a, b) => BoneAnim::Unk(a.clone(), b.clone()),
+            BoneAnim::Position(position) => BoneAnim::Position(flip_position(position)),
+            BoneAnim::PositionRotation { position, rotation } => BoneAnim::PositionRotation {
+                position: flip_position(position),
+                rotation: flip_rotation(rotation),
+            },
+            BoneAnim::RotationIk { target, rotation } => BoneAnim::RotationIk {
+                target: flip_position(target),
+                rotation: flip_rotation(rotation),
+            },
+            BoneAnim::ArmIk { target, rotation } => BoneAnim::ArmIk {
+                target: flip_position(target),
+                rotation: flip_rotation(rotation),
+            },
+            BoneAnim::LegIk { position, target } => BoneAnim::LegIk {
+                position: flip_position(position),
+                target: flip_position(target),
+            },
+        }
+    }
+}
+
+impl<'a> Motion<'a> {
+    ///Mirrors the motion left to right on `skeleton`.
+    ///
+    ///Bones with an `l` or `r` part in their name trade animations with their counterpart on
+    ///the other side, found in the bone ordering or else in `skeleton`. Animations are reflected
+    ///with [`BoneAnim::mirrored`], unless the skeleton gives both sides the same rest offsets,
+    ///in which case the curves already mean the mirrored motion and are kept as they are.
+    pub fn mirror(&self, skeleton: &Skeleton) -> Motion<'static> {
+        let anims = self
+            .anims
+            .iter()
+            .map(|(bone, anim)| {
+                let other = ordering::mirror(bone).map(str::to_string).or_else(|| {
+                    ordering::swap_sides(bone).filter(|name| skeleton.find(name).is_some())
+                });
+                let anim = anim.as_ref().map(|anim| {
+                    let sides = other
+                        .as_ref()
+                        .and_then(|other| Some((skeleton.get(bone)?, skeleton.get(other)?)));
+                    match sides {
+                        Some((a, b)) if mirrored_frames(a, b) => anim.clone(),
+                        _ => anim.mirrored(),
+                    }
+                });
+                let name = other.unwrap_or_else(|| bone.to_string());
+                (Bone(Cow::Owned(name)), anim)
+            })
+            .collect();
+        Motion {
+            frames: self.frames,
+            anims,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::channel::Component;
+    use diva_db::bone::BoneType;
+
+    #[test]
+    fn mirror_names() {
+        assert_eq!(ordering::mirror("kl_te_l_wj"), Some("kl_te_r_wj"));
+        assert_eq!(ordering::mirror("c_kata_r"), Some("c_kata_l"));
+        assert_eq!(ordering::mirror("nl_hito_b_l_wj"), Some("nl_hito_b_r_wj"));
+        assert_eq!(ordering::mirror("n_mabu_l_d_r"), Some("n_mabu_r_d_l"));
+        assert_eq!(ordering::mirror("kl_kubi"), None);
+        assert_eq!(ordering::mirror("gblctr"), None);
+    }
+
+    #[test]
+    fn mirror_swaps_sides() {
+        let ik = |x| BoneAnim::ArmIk {
+            target: (FrameData::Pose(x), FrameData::Pose(1.), FrameData::Pose(2.)),
+            rotation: (
+                FrameData::Pose(0.),
+                FrameData::Pose(0.5),
+                FrameData::Pose(0.25),
+            ),
+        };
+        let mut anims = BTreeMap::new();
+        anims.insert(Bone("c_kata_l".into()), Some(ik(0.3)));
+        anims.insert(Bone("c_kata_r".into()), Some(ik(-0.4)));
+        let mot = Motion { frames: 1, anims }.mirror(&Skeleton::default());
+
+        let right = mot.anim("c_kata_r").unwrap();
+        assert_eq!(
+            right.component(Component::Target).unwrap().0,
+            FrameData::Pose(-0.3)
+        );
+        assert_eq!(
+            right.component(Component::Rotation).unwrap().1,
+            FrameData::Pose(-0.5)
+        );
+        let left = mot.anim("c_kata_l").unwrap();
+        assert_eq!(
+            left.component(Component::Target).unwrap().0,
+            FrameData::Pose(0.4)
+        );
+    }
+
+    fn skeleton(flip: f32) -> Skeleton<'static> {
+        let arm = |x: f32| {
+            [
+                [0.2 * x, 0.4, 0.],
+                [0.3 * x, 0., -0.05],
+                [0.25 * x, 0., 0.05],
+            ]
+        };
+        let leg = |x: f32| [[0.1 * x, 0., 0.], [0., -0.4, 0.03], [0., -0.4, -0.03]];
+        Skeleton::test(&[
+            ("n_hara_cp", BoneType::Type3, None, &[[0., 1., 0.]]),
+            ("c_kata_l", BoneType::Type5, Some(0), &arm(1.)),
+            ("c_kata_r", BoneType::Type5, Some(0), &arm(flip)),
+            ("cl_momo_l", BoneType::Type6, Some(0), &leg(1.)),
+            ("cl_momo_r", BoneType::Type6, Some(0), &leg(flip)),
+        ])
+    }
+
+    fn motion() -> Motion<'static> {
+        let v = |x, y, z| (FrameData::Pose(x), FrameData::Pose(y), FrameData::Pose(z));
+        let mut anims = BTreeMap::new();
+        anims.insert(
+            Bone("n_hara_cp".into()),
+            Some(BoneAnim::PositionRotation {
+                position: v(0.1, 1., 0.2),
+                rotation: v(0.1, 0.3, -0.2),
+            }),
+        );
+        anims.insert(
+            Bone("c_kata_l".into()),
+            Some(BoneAnim::ArmIk {
+                target: v(0.6, 0.3, 0.2),
+                rotation: v(0.4, 0.2, 0.1),
+            }),
+        );
+        anims.insert(
+            Bone("c_kata_r".into()),
+            Some(BoneAnim::ArmIk {
+                target: v(-0.3, 0.1, 0.4),
+                rotation: v(-0.2, 0., 0.3),
+            }),
+        );
+        anims.insert(
+            Bone("cl_momo_l".into()),
+            Some(BoneAnim::LegIk {
+                position: v(0.12, 0., 0.),
+                target: v(0.15, -0.7, 0.2),
+            }),
+        );
+        anims.insert(
+            Bone("cl_momo_r".into()),
+            Some(BoneAnim::LegIk {
+                position: v(-0.1, 0., 0.),
+                target: v(-0.1, -0.75, -0.1),
+            }),
+        );
+        Motion { frames: 1, anims }
+    }
+
+    #[test]
+    fn mirror_matches_world_positions() {
+        let skeleton = skeleton(-1.);
+        let fk = skeleton.without_ik();
+        let mot = motion();
+        let mirrored = mot.mirror(&skeleton);
+        assert_eq!(
+            mirrored
+                .anim("c_kata_r")
+                .unwrap()
+                .component(Component::Target)
+                .unwrap()
+                .0,
+            FrameData::Pose(-0.6)
+        );
+
+        let pose = mot.bake_ik(&skeleton, 1e-5).world_pose(&fk, 0.);
+        let back = mirrored.bake_ik(&skeleton, 1e-5).world_pose(&fk, 0.);
+        for bone in &fk.bones {
+            let other = ordering::swap_sides(&bone.name).unwrap_or_else(|| bone.name.to_string());
+            let [x, y, z] = pose.get(&bone.name).unwrap().translation;
+            let b = back.get(&other).unwrap().translation;
+            assert!(
+                math::length(math::sub([-x, y, z], b)) < 1e-3,
+                "{} at {:?}, {} at {:?}",
+                bone.name,
+                [x, y, z],
+                other,
+                b
+            );
+        }
+    }
+
+    #[test]
+    fn mirrored_frames_keep_curves() {
+        let mot = motion();
+        let mirrored = mot.mirror(&skeleton(1.));
+        assert_eq!(mirrored.anim("c_kata_r"), mot.anim("c_kata_l"));
+        assert_eq!(mirrored.anim("cl_momo_l"), mot.anim("cl_momo_r"));
+        assert_ne!(mirrored.anim("n_hara_cp"), mot.anim("n_hara_cp"));
+    }
+}
@@ -254,6 +254,32 @@ static ORDERING: phf::Map<&'static str, u16> = phf_map! {
     "n_hara_c_wj_ex" => 248,
 };
 
+pub(crate) fn names() -> impl Iterator<Item = &'static str> {
+    ORDERING.keys().copied()
+}
+
+///Swaps the `l` and `r` parts of a bone name, if it has any
+pub(crate) fn swap_sides(name: &str) -> Option<String> {
+    let swapped = name
+        .split('_')
+        .map(|x| match x {
+            "l" => "r",
+            "r" => "l",
+            x => x,
+        })
+        .collect::<Vec<_>>()
+        .join("_");
+    if swapped == name {
+        return None;
+    }
+    Some(swapped)
+}
+
+///Returns the bone on the opposite side of the body, by swapping the `l` and `r` parts of the name
+pub(crate) fn mirror(name: &str) -> Option<&'static str> {
+    ORDERING.get_key(&swap_sides(name)?[..]).copied()
+}
+
 impl PartialOrd for Bone<'_> {
     fn partial_cmp(&self, other: &Self) -> Option<std::cmp::Ordering> {
         ORDERING
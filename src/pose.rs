@@ -0,0 +1,162 @@
+use super::*;
+use crate::blend::sample;
+use crate::ik::{solve_chain, IkChain};
+use crate::math::{Quat, Transform};
+use crate::skeleton::Skeleton;
+
+///World space transforms of every skeleton bone at one frame
+#[derive(Clone, PartialEq, Debug, Default)]
+pub struct WorldPose {
+    ///The transform every root bone hangs off, built from `gblctr` and `kg_ya_ex`
+    pub root: Transform,
+    pub bones: BTreeMap<String, Transform>,
+    ///Where the chain of each IK bone ends. Children of IK bones hang off their effector
+    pub effectors: BTreeMap<String, Transform>,
+    ///The solved joints of each IK bone that has a chain in the skeleton
+    pub chains: BTreeMap<String, IkChain>,
+}
+
+impl WorldPose {
+    pub fn get(&self, bone: &str) -> Option<Transform> {
+        self.bones.get(bone).copied()
+    }
+
+    pub fn effector(&self, bone: &str) -> Option<Transform> {
+        self.effectors.get(bone).copied()
+    }
+}
+
+///The local transform of a bone and, for IK bones, of the end of its chain.
+///
+///Positions and IK targets are in the space of the bone's parent. Bones without an animation
+///stay at rest.
+pub(crate) fn local_transform(
+    anim: Option<&BoneAnim>,
+    rest: [f32; 3],
+    frame: f32,
+) -> (Transform, Option<Transform>) {
+    let euler = |v| Quat::from_euler(sample(v, frame));
+    match anim {
+        None | Some(BoneAnim::Unk(_, _)) => (Transform::new(rest, Quat::IDENTITY), None),
+        Some(BoneAnim::Rotation(r)) => (Transform::new(rest, euler(r)), None),
+        Some(BoneAnim::Position(p)) => (Transform::new(sample(p, frame), Quat::IDENTITY), None),
+        Some(BoneAnim::PositionRotation { position, rotation }) => (
+            Transform::new(sample(position, frame), euler(rotation)),
+            None,
+        ),
+        Some(BoneAnim::RotationIk { target, rotation })
+        | Some(BoneAnim::ArmIk { target, rotation }) => {
+            let root = Transform::new(rest, euler(rotation));
+            let end = Transform::new(sample(target, frame), euler(rotation));
+            (root, Some(end))
+        }
+        Some(BoneAnim::LegIk { position, target }) => {
+            let root = Transform::new(sample(position, frame), Quat::IDENTITY);
+            let end = Transform::new(sample(target, frame), Quat::IDENTITY);
+            (root, Some(end))
+        }
+    }
+}
+
+impl<'a> Motion<'a> {
+    ///The transform every root bone hangs off at `frame`, built from `gblctr` and `kg_ya_ex`
+    pub fn root_transform(&self, frame: f32) -> Transform {
+        let gblctr = self.anim("gblctr");
+        let kg_ya_ex = self.anim("kg_ya_ex");
+        local_transform(gblctr, [0.; 3], frame).0 * local_transform(kg_ya_ex, [0.; 3], frame).0
+    }
+
+    ///Evaluates the world space transform of every bone of `skeleton` at `frame`.
+    ///
+    ///IK bones with a chain in the skeleton are solved with [`solve_chain`], others are taken to
+    ///reach their target exactly.
+    pub fn world_pose(&self, skeleton: &Skeleton, frame: f32) -> WorldPose {
+        let root = self.root_transform(frame);
+        let mut pose = WorldPose {
+            root,
+            ..Default::default()
+        };
+        //Parents aren't guaranteed to come first, so resolve bones as their parents become known
+        let mut done = vec![false; skeleton.bones.len()];
+        let mut remaining = skeleton.bones.len();
+        while remaining > 0 {
+            let before = remaining;
+            for (i, bone) in skeleton.bones.iter().enumerate() {
+                if done[i] {
+                    continue;
+                }
+                let parent = match bone.parent {
+                    None => root,
+                    Some(p) if done[p] => {
+                        let name = &skeleton.bones[p].name[..];
+                        pose.effector(name).or_else(|| pose.get(name)).unwrap()
+                    }
+                    Some(_) => continue,
+                };
+                let (local, end) = local_transform(self.anim(&bone.name), bone.rest(), frame);
+                let world = parent * local;
+                pose.bones.insert(bone.name.to_string(), world);
+                if let Some(end) = end {
+                    let mut effector = parent * end;
+                    if bone.positions.len() > 1 {
+                        let joints = solve_chain(world, &bone.positions[1..], effector.translation);
+                        effector = *joints.last().unwrap();
+                        pose.chains
+                            .insert(bone.name.to_string(), IkChain { parent, joints });
+                    }
+                    pose.effectors.insert(bone.name.to_string(), effector);
+                }
+                done[i] = true;
+                remaining -= 1;
+            }
+            if remaining == before {
+                //Cycle in the hierarchy, leave the rest unresolved
+                break;
+            }
+        }
+        pose
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::math::{length, sub};
+    use diva_db::bone::BoneType;
+
+    fn skeleton() -> Skeleton<'static> {
+        Skeleton::test(&[
+            ("n_hara_cp", BoneType::Type3, None, &[[0., 1., 0.]]),
+            ("kl_kubi", BoneType::Rotation, Some(0), &[[0., 0.5, 0.]]),
+            ("n_kao", BoneType::Rotation, Some(1), &[[0., 0.2, 0.]]),
+        ])
+    }
+
+    #[test]
+    fn chain_follows_parents() {
+        let v = |x, y, z| (FrameData::Pose(x), FrameData::Pose(y), FrameData::Pose(z));
+        let mut anims = BTreeMap::new();
+        anims.insert(
+            Bone("gblctr".into()),
+            Some(BoneAnim::Position(v(1., 0., 0.))),
+        );
+        anims.insert(
+            Bone("n_hara_cp".into()),
+            Some(BoneAnim::PositionRotation {
+                position: v(0., 1., 0.),
+                rotation: v(0., 0., 0.),
+            }),
+        );
+        let quarter = core::f32::consts::FRAC_PI_2;
+        anims.insert(
+            Bone("kl_kubi".into()),
+            Some(BoneAnim::Rotation(v(0., 0., quarter))),
+        );
+        let mot = Motion { frames: 1, anims };
+
+        let pose = mot.world_pose(&skeleton(), 0.);
+        let head = pose.get("n_kao").unwrap().translation;
+        //The neck turns the head over to -X
+        assert!(length(sub(head, [0.8, 1.5, 0.])) < 1e-5);
+    }
+}
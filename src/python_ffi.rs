@@ -49,11 +49,26 @@ pub fn write_all_bytes(raws: Vec<RawMotion>) -> Result<Vec<u8>, std::io::Error>
     Ok(data.into_inner())
 }
 
+#[pyfunction]
+fn to_hermite(keys: KeySet) -> KeySet {
+    Keyframe::from_frame_data(keyset2framedata(keys).into_hermite())
+}
+
+#[pyfunction]
+fn to_catmull_rom(keys: KeySet, tolerance: f32) -> Option<KeySet> {
+    keyset2framedata(keys)
+        .into_catmull_rom(tolerance)
+        .ok()
+        .map(Keyframe::from_frame_data)
+}
+
 #[pymodule]
 fn mot(_py: Python<'_>, m: &PyModule) -> PyResult<()> {
     m.add_wrapped(wrap_pyfunction!(read_raw_mot))?;
     m.add_wrapped(wrap_pyfunction!(read_mot))?;
     m.add_wrapped(wrap_pyfunction!(write_all_bytes))?;
+    m.add_wrapped(wrap_pyfunction!(to_hermite))?;
+    m.add_wrapped(wrap_pyfunction!(to_catmull_rom))?;
     m.add_class::<RawMotion>()?;
     m.add_class::<Motion>()?;
     m.add_class::<BoneAnim>()?;
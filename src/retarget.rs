@@ -0,0 +1,207 @@
+use super::*;
+use crate::channel::Component;
+use crate::ik::is_ik;
+use crate::math::{self, Vector3};
+use crate::skeleton::Skeleton;
+
+///Renames bones while retargeting. Bones without an entry keep their name
+#[derive(Clone, PartialEq, Eq, Debug, Default)]
+pub struct BoneMap {
+    names: BTreeMap<String, String>,
+}
+
+impl BoneMap {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn insert(&mut self, from: &str, to: &str) {
+        self.names.insert(from.to_string(), to.to_string());
+    }
+
+    ///The name `bone` has on the target skeleton
+    pub fn get<'b>(&'b self, bone: &'b str) -> &'b str {
+        self.names.get(bone).map(|x| &x[..]).unwrap_or(bone)
+    }
+
+    ///The map going the other way. Of several bones renamed to the same name, the one that sorts
+    ///first gets it back
+    pub fn inverse(&self) -> Self {
+        let mut names = BTreeMap::new();
+        for (x, y) in &self.names {
+            names.entry(y.clone()).or_insert_with(|| x.clone());
+        }
+        Self { names }
+    }
+}
+
+impl<S: AsRef<str>, T: AsRef<str>> core::iter::FromIterator<(S, T)> for BoneMap {
+    fn from_iter<I: IntoIterator<Item = (S, T)>>(iter: I) -> Self {
+        Self {
+            names: iter
+                .into_iter()
+                .map(|(x, y)| (x.as_ref().to_string(), y.as_ref().to_string()))
+                .collect(),
+        }
+    }
+}
+
+///Bones that couldn't be carried over by [`Motion::retarget`]
+#[derive(Clone, PartialEq, Eq, Debug, Default)]
+pub struct RetargetReport {
+    ///Animated bones the target skeleton doesn't have, which were dropped
+    pub dropped: Vec<String>,
+    ///Bones of the target skeleton the motion doesn't animate, which stay at rest
+    pub unanimated: Vec<String>,
+}
+
+///Bones that place the whole character and aren't part of any skeleton
+const ROOTS: [&str; 2] = ["gblctr", "kg_ya_ex"];
+
+///The summed length of an IK bone's chain
+fn chain_length(skeleton: &Skeleton, bone: &str) -> Option<f32> {
+    let bone = skeleton.get(bone).filter(|b| is_ik(b.mode))?;
+    Some(bone.positions[1..].iter().map(|&x| math::length(x)).sum())
+}
+
+///The average leg length, which sets the scale of the whole character
+fn leg_length(skeleton: &Skeleton) -> Option<f32> {
+    let legs: Vec<f32> = ["cl_momo_l", "cl_momo_r"]
+        .iter()
+        .filter_map(|x| chain_length(skeleton, x))
+        .collect();
+    match legs.len() {
+        0 => skeleton
+            .get("n_hara_cp")
+            .map(|b| b.rest()[1])
+            .filter(|&x| x > 0.),
+        n => Some(legs.iter().sum::<f32>() / n as f32),
+    }
+}
+
+///Scales `vec` by `ratio` around the rest position, moving it from `from` to `to`
+fn rescale(vec: &Vec3, from: Vector3, to: Vector3, ratio: f32) -> Vec3 {
+    let axis = |data: &FrameData, i: usize| data.scaled(ratio).translated(to[i] - from[i] * ratio);
+    (axis(&vec.0, 0), axis(&vec.1, 1), axis(&vec.2, 2))
+}
+
+impl<'a> Motion<'a> {
+    ///Moves the motion from skeleton `from` onto skeleton `to`, renaming bones through `map`.
+    ///
+    ///Rotations are kept as is. The root and every other position are scaled around their rest
+    ///position by the ratio of the skeletons' leg lengths, and IK targets by the ratio of their
+    ///own chain lengths, so feet stay planted and hands reach the same places on the body.
+    pub fn retarget(
+        &self,
+        from: &Skeleton,
+        to: &Skeleton,
+        map: &BoneMap,
+    ) -> (Motion<'a>, RetargetReport) {
+        let scale = match (leg_length(from), leg_length(to)) {
+            (Some(a), Some(b)) => b / a,
+            _ => 1.,
+        };
+        let mut report = RetargetReport::default();
+        let mut anims = BTreeMap::new();
+        for (bone, anim) in &self.anims {
+            let name = map.get(bone);
+            if ROOTS.contains(&name) {
+                let anim = anim.as_ref().map(|a| a.map(|_, _, d| d.scaled(scale)));
+                anims.insert(Bone(Cow::Owned(name.to_string())), anim);
+                continue;
+            }
+            let target = match to.get(name) {
+                Some(target) => target,
+                None => {
+                    report.dropped.push(bone.to_string());
+                    continue;
+                }
+            };
+            let rest_to = target.rest();
+            let rest_from = from.get(bone).map(|b| b.rest()).unwrap_or(rest_to);
+            let reach = match (chain_length(from, bone), chain_length(to, name)) {
+                (Some(a), Some(b)) if a > 0. => b / a,
+                _ => scale,
+            };
+            let anim = anim.as_ref().map(|anim| {
+                let mut anim = anim.clone();
+                for (component, vec) in anim.components_mut() {
+                    let ratio = match component {
+                        Component::Position => scale,
+                        Component::Target => reach,
+                        _ => continue,
+                    };
+                    *vec = rescale(vec, rest_from, rest_to, ratio);
+                }
+                anim
+            });
+            anims.insert(Bone(Cow::Owned(name.to_string())), anim);
+        }
+        report.unanimated = to
+            .bones
+            .iter()
+            .filter(|b| !anims.keys().any(|x: &Bone| x[..] == b.name[..]))
+            .map(|b| b.name.to_string())
+            .collect();
+        let mot = Motion {
+            frames: self.frames,
+            anims,
+        };
+        (mot, report)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use diva_db::bone::BoneType;
+
+    fn skeleton(size: f32) -> Skeleton<'static> {
+        let leg = [[0.1, 0., 0.], [0., -size / 2., 0.], [0., -size / 2., 0.]];
+        Skeleton::test(&[
+            ("n_hara_cp", BoneType::Type3, None, &[[0., size, 0.]]),
+            ("cl_momo_l", BoneType::Type6, Some(0), &leg),
+            ("kl_kubi", BoneType::Rotation, Some(0), &[[0., 0.5, 0.]]),
+        ])
+    }
+
+    #[test]
+    fn feet_stay_planted() {
+        let v = |x, y, z| (FrameData::Pose(x), FrameData::Pose(y), FrameData::Pose(z));
+        let mut anims = BTreeMap::new();
+        anims.insert(
+            Bone("gblctr".into()),
+            Some(BoneAnim::Position(v(2., 0., 0.))),
+        );
+        anims.insert(
+            Bone("n_hara_cp".into()),
+            Some(BoneAnim::PositionRotation {
+                position: v(0., 0.9, 0.),
+                rotation: v(0., 0., 0.),
+            }),
+        );
+        anims.insert(
+            Bone("cl_momo_l".into()),
+            Some(BoneAnim::LegIk {
+                position: v(0.1, 0., 0.),
+                target: v(0.1, -0.9, 0.2),
+            }),
+        );
+        anims.insert(
+            Bone("j_tail".into()),
+            Some(BoneAnim::Rotation(v(1., 0., 0.))),
+        );
+        let mot = Motion { frames: 1, anims };
+
+        let (out, report) = mot.retarget(&skeleton(1.), &skeleton(2.), &BoneMap::new());
+        assert_eq!(report.dropped, vec!["j_tail".to_string()]);
+        assert_eq!(report.unanimated, vec!["kl_kubi".to_string()]);
+
+        let pose = out.world_pose(&skeleton(2.), 0.);
+        let foot = pose.effector("cl_momo_l").unwrap().translation;
+        //Twice as tall, twice the stride, and the foot still touches the floor
+        assert!((foot[0] - 4.1).abs() < 1e-4);
+        assert!(foot[1].abs() < 1e-4);
+        assert!((foot[2] - 0.4).abs() < 1e-4);
+    }
+}
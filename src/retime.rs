@@ -0,0 +1,243 @@
+use super::*;
+
+#[derive(Copy, Clone, PartialEq, PartialOrd, Debug, Error)]
+pub enum TempoMapError {
+    #[error("Tempo map needs at least 2 points")]
+    TooFewPoints,
+    #[error("Tempo map points must strictly increase, found ({0}, {1}) after a later point")]
+    NotIncreasing(f32, f32),
+    #[error("Tempo map point ({0}, {1}) is not finite")]
+    NotFinite(f32, f32),
+}
+
+///A piecewise linear mapping from source frames to destination frames.
+///
+///Frames before the first or after the last point are extrapolated from the nearest segment.
+#[derive(Clone, PartialEq, PartialOrd, Debug)]
+pub struct TempoMap {
+    points: Vec<(f32, f32)>,
+}
+
+impl TempoMap {
+    pub fn new(points: Vec<(f32, f32)>) -> Result<Self, TempoMapError> {
+        if points.len() < 2 {
+            return Err(TempoMapError::TooFewPoints);
+        }
+        if let Some(&(s, d)) = points
+            .iter()
+            .find(|(s, d)| !s.is_finite() || !d.is_finite())
+        {
+            return Err(TempoMapError::NotFinite(s, d));
+        }
+        for w in points.windows(2) {
+            let ((s0, d0), (s1, d1)) = (w[0], w[1]);
+            if s1 <= s0 || d1 <= d0 {
+                return Err(TempoMapError::NotIncreasing(s1, d1));
+            }
+        }
+        Ok(Self { points })
+    }
+
+    ///Uniformly stretches time, e.g. `2.` plays twice as slow. `factor` must be positive
+    pub fn scale(factor: f32) -> Result<Self, TempoMapError> {
+        Self::new(vec![(0., 0.), (1., factor)])
+    }
+
+    ///Converts a motion authored at `from` frames per second to `to` frames per second
+    pub fn frame_rate(from: f32, to: f32) -> Result<Self, TempoMapError> {
+        Self::scale(to / from)
+    }
+
+    ///Maps a source frame to its destination frame
+    pub fn map(&self, frame: f32) -> f32 {
+        Self::lerp(&self.points, frame, |(s, d)| (s, d))
+    }
+
+    ///Maps a destination frame back to its source frame
+    pub fn inverse(&self, frame: f32) -> f32 {
+        Self::lerp(&self.points, frame, |(s, d)| (d, s))
+    }
+
+    fn lerp<F>(points: &[(f32, f32)], x: f32, f: F) -> f32
+    where
+        F: Fn((f32, f32)) -> (f32, f32),
+    {
+        let i = points
+            .iter()
+            .skip(1)
+            .position(|&p| f(p).0 > x)
+            .unwrap_or(points.len() - 2);
+        let (x0, y0) = f(points[i]);
+        let (x1, y1) = f(points[i + 1]);
+        y0 + (x - x0) * (y1 - y0) / (x1 - x0)
+    }
+}
+
+impl FrameData {
+    ///Fits a `Hermite` curve through one sample per frame starting at `start`.
+    ///
+    ///Keys are added until every sample is within `tolerance` of the curve.
+    ///Constant samples become a `Pose`. Samples past frame `u16::MAX` are cut off.
+    pub fn fit(start: u16, samples: &[f32], tolerance: f32) -> Self {
+        let samples = &samples[..samples.len().min((u16::MAX - start) as usize + 1)];
+        let first = match samples.first() {
+            Some(&first) => first,
+            None => return FrameData::None,
+        };
+        if samples.iter().all(|&x| (x - first).abs() <= tolerance) {
+            return FrameData::Pose(first);
+        }
+        let last = samples.len() - 1;
+        let tangents: Vec<f32> = (0..samples.len())
+            .map(|i| {
+                let (a, b) = (i.saturating_sub(1), (i + 1).min(last));
+                (samples[b] - samples[a]) / (b - a) as f32
+            })
+            .collect();
+        let mut indices = vec![0];
+        fit_segment(samples, &tangents, 0, last, tolerance, &mut indices);
+        let keys = indices
+            .into_iter()
+            .map(|i| Keyframe {
+                frame: start + i as u16,
+                value: samples[i],
+                interpolation: tangents[i],
+            })
+            .collect();
+        FrameData::Hermite(keys)
+    }
+
+    ///Warps the curve in time through `map`, refitting it on the destination frames
+    pub fn retimed(&self, map: &TempoMap, tolerance: f32) -> Self {
+        let frames = self.key_frames();
+        let (first, last) = match (frames.first(), frames.last()) {
+            (Some(&first), Some(&last)) => (first, last),
+            _ => return self.clone(),
+        };
+        let clamp = |x: f32| x.round().max(0.).min(u16::MAX as f32) as u16;
+        let start = clamp(map.map(first as f32));
+        let end = clamp(map.map(last as f32));
+        let samples: Vec<f32> = (start..=end)
+            .map(|f| self.evaluate(map.inverse(f as f32)).unwrap_or_default())
+            .collect();
+        match Self::fit(start, &samples, tolerance) {
+            FrameData::Pose(value) if start != 0 => {
+                //Keep the curve keyed so it still starts at the mapped frame
+                let mut data = FrameData::None;
+                data.insert_key(start, value);
+                data
+            }
+            x => x,
+        }
+    }
+}
+
+fn fit_segment(
+    samples: &[f32],
+    tangents: &[f32],
+    a: usize,
+    b: usize,
+    tolerance: f32,
+    indices: &mut Vec<usize>,
+) {
+    let dt = (b - a) as f32;
+    let (p0, p1) = (samples[a], samples[b]);
+    let (m0, m1) = (tangents[a] * dt, tangents[b] * dt);
+    let worst = (a + 1..b)
+        .map(|i| {
+            let t = (i - a) as f32 / dt;
+            let (t2, t3) = (t * t, t * t * t);
+            let value = (2. * t3 - 3. * t2 + 1.) * p0
+                + (t3 - 2. * t2 + t) * m0
+                + (-2. * t3 + 3. * t2) * p1
+                + (t3 - t2) * m1;
+            (i, (value - samples[i]).abs())
+        })
+        .fold(None, |acc: Option<(usize, f32)>, x| match acc {
+            Some(y) if y.1 >= x.1 => Some(y),
+            _ => Some(x),
+        });
+    match worst {
+        Some((i, err)) if err > tolerance => {
+            fit_segment(samples, tangents, a, i, tolerance, indices);
+            fit_segment(samples, tangents, i, b, tolerance, indices);
+        }
+        _ => indices.push(b),
+    }
+}
+
+impl<'a> Motion<'a> {
+    ///Warps the motion in time through `map`, refitting every curve within `tolerance`
+    pub fn retime(&mut self, map: &TempoMap, tolerance: f32) {
+        for (_, data) in self.channels_mut() {
+            *data = data.retimed(map, tolerance);
+        }
+        let last = map.map(self.frames.saturating_sub(1) as f32).round();
+        self.frames = (last + 1.).max(0.).min(u16::MAX as f32) as u16;
+    }
+
+    ///Converts the motion from `from` frames per second to `to` frames per second
+    pub fn resample(&mut self, from: f32, to: f32, tolerance: f32) -> Result<(), TempoMapError> {
+        self.retime(&TempoMap::frame_rate(from, to)?, tolerance);
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn tempo_map() {
+        let map = TempoMap::new(vec![(0., 0.), (10., 20.), (20., 25.)]).unwrap();
+        assert_eq!(map.map(5.), 10.);
+        assert_eq!(map.map(15.), 22.5);
+        assert_eq!(map.map(30.), 30.);
+        assert_eq!(map.inverse(22.5), 15.);
+        assert_eq!(
+            TempoMap::new(vec![(0., 0.), (0., 1.)]),
+            Err(TempoMapError::NotIncreasing(0., 1.))
+        );
+        assert_eq!(
+            TempoMap::scale(0.),
+            Err(TempoMapError::NotIncreasing(1., 0.))
+        );
+        assert_eq!(
+            TempoMap::scale(-2.),
+            Err(TempoMapError::NotIncreasing(1., -2.))
+        );
+        assert!(matches!(
+            TempoMap::frame_rate(0., 60.),
+            Err(TempoMapError::NotFinite(_, _))
+        ));
+        assert!(TempoMap::frame_rate(0., 0.).is_err());
+    }
+
+    #[test]
+    fn fit_within_tolerance() {
+        let samples: Vec<f32> = (0..100).map(|x| (x as f32 / 10.).sin()).collect();
+        let data = FrameData::fit(0, &samples, 1e-3);
+        assert!(data.key_frames().len() < samples.len() / 2);
+        for (i, x) in samples.iter().enumerate() {
+            assert!((data.evaluate(i as f32).unwrap() - x).abs() <= 1e-3);
+        }
+        assert_eq!(FrameData::fit(0, &[1., 1.], 0.), FrameData::Pose(1.));
+        let data = FrameData::fit(u16::MAX - 1, &[0., 1., 4., 9.], 0.);
+        assert_eq!(data.key_frames(), vec![u16::MAX - 1, u16::MAX]);
+    }
+
+    #[test]
+    fn retime_scales_keys() {
+        let mut data = FrameData::None;
+        data.insert_key(0, 0.);
+        data.insert_key(10, 1.);
+        data.insert_key(20, 0.);
+        let slow = data.retimed(&TempoMap::scale(2.).unwrap(), 1e-4);
+        assert_eq!(slow.key_frames().first(), Some(&0));
+        assert_eq!(slow.key_frames().last(), Some(&40));
+        for f in 0..=20 {
+            let (a, b) = (data.evaluate(f as f32), slow.evaluate(f as f32 * 2.));
+            assert!((a.unwrap() - b.unwrap()).abs() <= 1e-4);
+        }
+    }
+}
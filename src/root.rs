@@ -0,0 +1,195 @@
+use super::*;
+use crate::blend::{fit, sample, to_euler};
+use crate::math::{Quat, Transform, Vector3};
+use crate::pose::local_transform;
+use core::f32::consts::PI;
+
+///The path of a performer across the stage: where the hips are over the floor and which way
+///they face
+#[derive(Clone, PartialEq, Debug)]
+pub struct RootTrack {
+    ///The hips projected onto the height of `gblctr`
+    pub position: Vec3,
+    ///The heading of the hips around the Y axis, 0 facing +Z
+    pub yaw: FrameData,
+}
+
+impl RootTrack {
+    pub fn transform(&self, frame: f32) -> Transform {
+        let yaw = self.yaw.evaluate(frame).unwrap_or_default();
+        Transform::new(
+            sample(&self.position, frame),
+            Quat::from_axis_angle([0., 1., 0.], yaw),
+        )
+    }
+}
+
+fn pose(vec: Vector3) -> Vec3 {
+    (
+        FrameData::Pose(vec[0]),
+        FrameData::Pose(vec[1]),
+        FrameData::Pose(vec[2]),
+    )
+}
+
+impl<'a> Motion<'a> {
+    ///World transform of `n_hara_cp` at `frame`
+    fn hips(&self, frame: f32) -> Transform {
+        self.root_transform(frame) * local_transform(self.anim("n_hara_cp"), [0.; 3], frame).0
+    }
+
+    ///Replaces `gblctr` and `kg_ya_ex` with per frame transforms fitted to `tolerance`
+    fn set_root(&mut self, roots: &[Transform], tolerance: f32) {
+        let positions: Vec<Vector3> = roots.iter().map(|t| t.translation).collect();
+        let rotations: Vec<Quat> = roots.iter().map(|t| t.rotation).collect();
+        self.set_anim("gblctr", BoneAnim::Position(fit(0, &positions, tolerance)));
+        self.set_anim(
+            "kg_ya_ex",
+            BoneAnim::Rotation(fit(0, &to_euler(&rotations), tolerance)),
+        );
+    }
+
+    ///Replaces `n_hara_cp` with per frame transforms fitted to `tolerance`
+    fn set_hips(&mut self, hips: &[Transform], tolerance: f32) {
+        let positions: Vec<Vector3> = hips.iter().map(|t| t.translation).collect();
+        let rotations: Vec<Quat> = hips.iter().map(|t| t.rotation).collect();
+        self.set_anim(
+            "n_hara_cp",
+            BoneAnim::PositionRotation {
+                position: fit(0, &positions, tolerance),
+                rotation: fit(0, &to_euler(&rotations), tolerance),
+            },
+        );
+    }
+
+    fn set_anim(&mut self, bone: &str, anim: BoneAnim) {
+        match self.anim_mut(bone) {
+            Some(x) => *x = anim,
+            None => {
+                self.anims
+                    .insert(Bone(Cow::Owned(bone.to_string())), Some(anim));
+            }
+        }
+    }
+
+    ///Extracts the path of the hips over the floor from `gblctr`, `kg_ya_ex` and `n_hara_cp`
+    pub fn root_track(&self, tolerance: f32) -> RootTrack {
+        let mut positions = Vec::with_capacity(self.frames as usize);
+        let mut yaws: Vec<f32> = Vec::with_capacity(self.frames as usize);
+        for f in 0..self.frames.max(1) {
+            let hips = self.hips(f as f32);
+            let [x, _, z] = hips.translation;
+            positions.push([x, self.root_transform(f as f32).translation[1], z]);
+            let [fx, _, fz] = hips.rotation.rotate([0., 0., 1.]);
+            let mut yaw = fx.atan2(fz);
+            //Keep turning the same way instead of jumping back by a full turn
+            if let Some(&prev) = yaws.last() {
+                yaw += 2. * PI * ((prev - yaw) / (2. * PI)).round();
+            }
+            yaws.push(yaw);
+        }
+        let (x, y, z) = fit(0, &positions, tolerance);
+        RootTrack {
+            position: (x, y, z),
+            yaw: FrameData::fit(0, &yaws, tolerance),
+        }
+    }
+
+    ///Takes out `track`, leaving the motion in place at the stage origin facing +Z.
+    ///
+    ///`gblctr` and `kg_ya_ex` are zeroed and `n_hara_cp` is made relative to the track.
+    pub fn without_root(&self, track: &RootTrack, tolerance: f32) -> Self {
+        let hips: Vec<Transform> = (0..self.frames.max(1))
+            .map(|f| track.transform(f as f32).inverse() * self.hips(f as f32))
+            .collect();
+        let mut mot = self.clone();
+        mot.set_hips(&hips, tolerance);
+        mot.set_anim("gblctr", BoneAnim::Position(pose([0.; 3])));
+        mot.set_anim("kg_ya_ex", BoneAnim::Rotation(pose([0.; 3])));
+        mot
+    }
+
+    ///Puts `track` into `gblctr` and `kg_ya_ex`, the reverse of [`Motion::without_root`]
+    pub fn with_root(&self, track: &RootTrack, tolerance: f32) -> Self {
+        let frames = self.frames.max(1);
+        let roots: Vec<Transform> = (0..frames)
+            .map(|f| track.transform(f as f32) * self.root_transform(f as f32))
+            .collect();
+        let mut mot = self.clone();
+        mot.set_root(&roots, tolerance);
+        mot
+    }
+
+    ///Moves the whole performance by `offset` after turning it by `yaw` around the stage origin
+    pub fn place(&self, offset: Vector3, yaw: f32, tolerance: f32) -> Self {
+        let track = RootTrack {
+            position: pose(offset),
+            yaw: FrameData::Pose(yaw),
+        };
+        self.with_root(&track, tolerance)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::math::{length, sub};
+    use core::f32::consts::FRAC_PI_2;
+
+    fn close(a: Transform, b: Transform) -> bool {
+        length(sub(a.translation, b.translation)) < 1e-3
+            && a.rotation.dot(b.rotation).abs() > 1. - 1e-5
+    }
+
+    fn motion() -> Motion<'static> {
+        let line = |a: f32, b: f32| FrameData::fit(0, &[a, (a + b) / 2., b], 1e-5);
+        let mut anims = BTreeMap::new();
+        anims.insert(
+            Bone("gblctr".into()),
+            Some(BoneAnim::Position((
+                line(0., 2.),
+                line(0., 0.),
+                line(1., 3.),
+            ))),
+        );
+        anims.insert(
+            Bone("kg_ya_ex".into()),
+            Some(BoneAnim::Rotation(pose([0., 0.3, 0.]))),
+        );
+        anims.insert(
+            Bone("n_hara_cp".into()),
+            Some(BoneAnim::PositionRotation {
+                position: (line(0., 0.5), FrameData::Pose(1.), FrameData::Pose(0.)),
+                rotation: (FrameData::Pose(0.1), line(0., 1.), FrameData::Pose(0.)),
+            }),
+        );
+        Motion { frames: 3, anims }
+    }
+
+    #[test]
+    fn root_roundtrip() {
+        let mot = motion();
+        let track = mot.root_track(1e-5);
+        let in_place = mot.without_root(&track, 1e-5);
+        //Facing +Z above the origin
+        let hips = in_place.hips(2.);
+        assert!(hips.translation[0].abs() < 1e-4 && hips.translation[2].abs() < 1e-4);
+        let [fx, _, _] = hips.rotation.rotate([0., 0., 1.]);
+        assert!(fx.abs() < 1e-4);
+
+        let back = in_place.with_root(&track, 1e-5);
+        for f in 0..3 {
+            assert!(close(back.hips(f as f32), mot.hips(f as f32)));
+        }
+    }
+
+    #[test]
+    fn place_turns_around_origin() {
+        let mot = motion();
+        let placed = mot.place([5., 0., 0.], FRAC_PI_2, 1e-5);
+        let turn = Transform::new([5., 0., 0.], Quat::from_axis_angle([0., 1., 0.], FRAC_PI_2));
+        for f in 0..3 {
+            assert!(close(placed.hips(f as f32), turn * mot.hips(f as f32)));
+        }
+    }
+}
@@ -0,0 +1,87 @@
+use super::*;
+
+///Whether a keyframe's interpolation carries no data and can be left out
+pub(crate) fn is_unit<I>(_: &I) -> bool {
+    core::mem::size_of::<I>() == 0
+}
+
+///Writes the components of a `Vec3` by name instead of as a tuple
+pub(crate) mod vec3 {
+    use super::*;
+    use serde::{Deserialize, Deserializer, Serialize, Serializer};
+
+    #[derive(Serialize, Deserialize)]
+    struct Components<T> {
+        x: T,
+        y: T,
+        z: T,
+    }
+
+    pub fn serialize<S: Serializer>((x, y, z): &Vec3, serializer: S) -> Result<S::Ok, S::Error> {
+        Components { x, y, z }.serialize(serializer)
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Vec3, D::Error> {
+        let Components { x, y, z } = Components::deserialize(deserializer)?;
+        Ok((x, y, z))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn json_roundtrip() -> anyhow::Result<()> {
+        let mut anims = BTreeMap::new();
+        anims.insert(
+            Bone("n_hara_cp".into()),
+            Some(BoneAnim::PositionRotation {
+                position: (
+                    FrameData::None,
+                    FrameData::Pose(1.),
+                    FrameData::CatmulRom(vec![
+                        Keyframe {
+                            frame: 0,
+                            value: 0.,
+                            interpolation: (),
+                        },
+                        Keyframe {
+                            frame: 10,
+                            value: 0.5,
+                            interpolation: (),
+                        },
+                    ]),
+                ),
+                rotation: (
+                    FrameData::Hermite(vec![Keyframe {
+                        frame: 0,
+                        value: 0.1,
+                        interpolation: 0.2,
+                    }]),
+                    FrameData::Pose(0.),
+                    FrameData::Pose(0.),
+                ),
+            }),
+        );
+        anims.insert(Bone("kl_kubi".into()), None);
+        let mot = Motion { frames: 11, anims };
+
+        let json = serde_json::to_value(&mot)?;
+        let bone = &json["anims"]["n_hara_cp"]["position_rotation"];
+        assert_eq!(bone["position"]["x"], "none");
+        assert_eq!(bone["position"]["y"]["pose"], 1.);
+        assert_eq!(bone["position"]["z"]["catmul_rom"][1]["frame"], 10);
+        assert!(bone["position"]["z"]["catmul_rom"][1]
+            .get("tangent")
+            .is_none());
+        assert_eq!(
+            bone["rotation"]["x"]["hermite"][0]["tangent"],
+            0.2f32 as f64
+        );
+
+        let back: Motion = serde_json::from_value(json)?;
+        assert_eq!(back, mot);
+        Ok(())
+    }
+}
@@ -0,0 +1,112 @@
+use super::*;
+use crate::mask::BoneMask;
+use crate::math::Vector3;
+use diva_db::bone::BoneType;
+
+#[derive(Copy, Clone, PartialEq, Eq, PartialOrd, Ord, Debug, Error)]
+pub enum SkeletonError {
+    #[error("Found no skeleton in bone database")]
+    NoSkeleton,
+    #[error("Bone `{0}` points at a parent outside of the skeleton")]
+    BadParent(usize),
+    #[error("Not enough rest positions for bone `{0}`")]
+    MissingPosition(usize),
+}
+
+///The motion bones of a skeleton with their hierarchy and rest positions
+#[derive(Clone, PartialEq, Debug, Default)]
+pub struct Skeleton<'a> {
+    pub bones: Vec<SkeletonBone<'a>>,
+}
+
+#[derive(Clone, PartialEq, Debug)]
+pub struct SkeletonBone<'a> {
+    pub name: Cow<'a, str>,
+    pub mode: BoneType,
+    pub parent: Option<usize>,
+    ///The offset from the parent at rest. IK bones follow it with the rest offsets of each
+    ///joint along their chain, e.g. the elbow and the wrist for an arm.
+    pub positions: Vec<Vector3>,
+}
+
+impl<'a> Skeleton<'a> {
+    ///Reads the first skeleton of the database
+    pub fn from_db(bone_db: &BoneDatabase<'a>) -> Result<Self, SkeletonError> {
+        let skel = bone_db.skeletons.first().ok_or(SkeletonError::NoSkeleton)?;
+        let mut positions = skel.positions.iter().map(|&(x, y, z)| [x, y, z]);
+        let mut bones = Vec::with_capacity(skel.bones.len());
+        for (i, bone) in skel.bones.iter().enumerate() {
+            //IK bones store one extra position for each joint of their chain
+            let count = match bone.mode {
+                BoneType::Type4 => 2,
+                BoneType::Type5 | BoneType::Type6 => 3,
+                _ => 1,
+            };
+            let positions = (0..count)
+                .map(|_| positions.next().ok_or(SkeletonError::MissingPosition(i)))
+                .collect::<Result<_, _>>()?;
+            let parent = match bone.has_parent {
+                true if (bone.parent as usize) < skel.bones.len() => Some(bone.parent as usize),
+                true => return Err(SkeletonError::BadParent(i)),
+                false => None,
+            };
+            bones.push(SkeletonBone {
+                name: bone.name.clone(),
+                mode: bone.mode,
+                parent,
+                positions,
+            });
+        }
+        Ok(Self { bones })
+    }
+
+    pub fn find(&self, name: &str) -> Option<usize> {
+        self.bones.iter().position(|b| b.name == name)
+    }
+
+    pub fn get(&self, name: &str) -> Option<&SkeletonBone<'a>> {
+        self.find(name).map(|i| &self.bones[i])
+    }
+
+    pub fn children(&self, index: usize) -> impl Iterator<Item = usize> + '_ {
+        self.bones
+            .iter()
+            .enumerate()
+            .filter(move |(_, b)| b.parent == Some(index))
+            .map(|(i, _)| i)
+    }
+
+    ///A mask of `root` and every bone below it
+    pub fn subtree(&self, root: &str) -> BoneMask {
+        let mut mask = BoneMask::new();
+        let mut stack: Vec<usize> = self.find(root).into_iter().collect();
+        while let Some(i) = stack.pop() {
+            mask.insert(&self.bones[i].name);
+            stack.extend(self.children(i));
+        }
+        mask
+    }
+}
+
+impl SkeletonBone<'_> {
+    pub fn rest(&self) -> Vector3 {
+        self.positions.first().copied().unwrap_or_default()
+    }
+}
+
+#[cfg(test)]
+impl Skeleton<'static> {
+    ///Builds a skeleton for tests out of `(name, mode, parent, positions)` for each bone
+    pub(crate) fn test(bones: &[(&'static str, BoneType, Option<usize>, &[Vector3])]) -> Self {
+        let bones = bones
+            .iter()
+            .map(|&(name, mode, parent, positions)| SkeletonBone {
+                name: name.into(),
+                mode,
+                parent,
+                positions: positions.to_vec(),
+            })
+            .collect();
+        Self { bones }
+    }
+}
@@ -0,0 +1,419 @@
+//!A plain text form of motions meant for editing by hand and diffing.
+//!
+//!```text
+//!motion 120
+//!bone n_hara_cp position_rotation
+//!  position.x none
+//!  position.y pose 1
+//!  position.z catmull_rom
+//!    0 0
+//!    60 0.5
+//!  rotation.x hermite
+//!    0 0.1 0
+//!bone kl_kubi none
+//!```
+//!
+//!Raw motions list their bone ids and then one `set` per curve. Values are written with the
+//!shortest digits that read back to the same float, so converting back is lossless.
+use super::*;
+use crate::channel::{Axis, ChannelError, Component};
+
+use core::fmt;
+use core::str::FromStr;
+
+#[derive(Clone, PartialEq, Debug, Error)]
+pub enum TextError {
+    #[error("Line {0}: expected {1}")]
+    Expected(usize, &'static str),
+    #[error("Line {0}: `{1}` is not a number")]
+    BadNumber(usize, String),
+    #[error("Line {0}: unknown animation type `{1}`")]
+    UnknownAnim(usize, String),
+    #[error("Line {0}: unknown curve type `{1}`")]
+    UnknownCurve(usize, String),
+    #[error("Line {0}: {1}")]
+    Channel(usize, #[source] ChannelError),
+    #[error("Line {0}: bone has no channel `{1}`")]
+    NoChannel(usize, String),
+}
+
+///The non empty lines of a text, split into words
+struct Lines<'a> {
+    lines: Vec<(usize, Vec<&'a str>)>,
+    pos: usize,
+}
+
+impl<'a> Lines<'a> {
+    fn new(text: &'a str) -> Self {
+        let lines = text
+            .lines()
+            .enumerate()
+            .map(|(i, line)| (i + 1, line.split('#').next().unwrap_or_default()))
+            .map(|(i, line)| (i, line.split_whitespace().collect::<Vec<_>>()))
+            .filter(|(_, words)| !words.is_empty())
+            .collect();
+        Self { lines, pos: 0 }
+    }
+
+    fn peek(&self) -> Option<&(usize, Vec<&'a str>)> {
+        self.lines.get(self.pos)
+    }
+
+    fn next(&mut self) -> Option<(usize, Vec<&'a str>)> {
+        let line = self.lines.get(self.pos).cloned();
+        self.pos += 1;
+        line
+    }
+
+    ///Line number to report when the text ends early
+    fn end(&self) -> usize {
+        self.lines.last().map_or(1, |(i, _)| i + 1)
+    }
+
+    ///Takes the next line if it starts with `word`, described as `what` in errors
+    fn expect(
+        &mut self,
+        word: &str,
+        what: &'static str,
+    ) -> Result<(usize, Vec<&'a str>), TextError> {
+        match self.next() {
+            Some((i, words)) if words[0] == word => Ok((i, words)),
+            Some((i, _)) => Err(TextError::Expected(i, what)),
+            None => Err(TextError::Expected(self.end(), what)),
+        }
+    }
+
+    fn is_key(&self) -> bool {
+        match self.peek() {
+            Some((_, words)) => words[0].starts_with(|c: char| c.is_ascii_digit()),
+            None => false,
+        }
+    }
+
+    ///Reads a curve from its type words and the key lines following them
+    fn curve(&mut self, line: usize, words: &[&str]) -> Result<FrameData, TextError> {
+        let kind = *words
+            .first()
+            .ok_or(TextError::Expected(line, "a curve type"))?;
+        if words.len() != if kind == "pose" { 2 } else { 1 } {
+            return Err(TextError::Expected(
+                line,
+                "a curve type and value for poses",
+            ));
+        }
+        let data = match kind {
+            "none" => FrameData::None,
+            "pose" => FrameData::Pose(number(line, words[1])?),
+            "catmull_rom" => {
+                let mut keys = vec![];
+                while self.is_key() {
+                    let (i, words) = self.next().unwrap();
+                    match &words[..] {
+                        [frame, value] => keys.push(Keyframe {
+                            frame: number(i, frame)?,
+                            value: number(i, value)?,
+                            interpolation: (),
+                        }),
+                        _ => return Err(TextError::Expected(i, "a frame and a value")),
+                    }
+                }
+                FrameData::CatmulRom(keys)
+            }
+            "hermite" => {
+                let mut keys = vec![];
+                while self.is_key() {
+                    let (i, words) = self.next().unwrap();
+                    match &words[..] {
+                        [frame, value, tangent] => keys.push(Keyframe {
+                            frame: number(i, frame)?,
+                            value: number(i, value)?,
+                            interpolation: number(i, tangent)?,
+                        }),
+                        _ => return Err(TextError::Expected(i, "a frame, a value and a tangent")),
+                    }
+                }
+                FrameData::Hermite(keys)
+            }
+            _ => return Err(TextError::UnknownCurve(line, kind.to_string())),
+        };
+        Ok(data)
+    }
+}
+
+fn number<T: FromStr>(line: usize, word: &str) -> Result<T, TextError> {
+    word.parse()
+        .map_err(|_| TextError::BadNumber(line, word.to_string()))
+}
+
+///Writes the curve type and, indented below it, the keys
+fn write_curve(f: &mut fmt::Formatter<'_>, indent: &str, data: &FrameData) -> fmt::Result {
+    match data {
+        FrameData::None => writeln!(f, "none"),
+        FrameData::Pose(x) => writeln!(f, "pose {}", x),
+        FrameData::CatmulRom(keys) => {
+            writeln!(f, "catmull_rom")?;
+            for key in keys {
+                writeln!(f, "{}  {} {}", indent, key.frame, key.value)?;
+            }
+            Ok(())
+        }
+        FrameData::Hermite(keys) => {
+            writeln!(f, "hermite")?;
+            for key in keys {
+                writeln!(
+                    f,
+                    "{}  {} {} {}",
+                    indent, key.frame, key.value, key.interpolation
+                )?;
+            }
+            Ok(())
+        }
+    }
+}
+
+impl BoneAnim {
+    pub(crate) fn kind(&self) -> &'static str {
+        match self {
+            BoneAnim::Rotation(_) => "rotation",
+            BoneAnim::Unk(_, _) => "unk",
+            BoneAnim::Position(_) => "position",
+            BoneAnim::PositionRotation { .. } => "position_rotation",
+            BoneAnim::RotationIk { .. } => "rotation_ik",
+            BoneAnim::ArmIk { .. } => "arm_ik",
+            BoneAnim::LegIk { .. } => "leg_ik",
+        }
+    }
+
+    ///An animation of type `kind` with no curves
+    pub(crate) fn empty(kind: &str) -> Option<Self> {
+        let v = || (FrameData::None, FrameData::None, FrameData::None);
+        let anim = match kind {
+            "rotation" => BoneAnim::Rotation(v()),
+            "unk" => BoneAnim::Unk(v(), v()),
+            "position" => BoneAnim::Position(v()),
+            "position_rotation" => BoneAnim::PositionRotation {
+                position: v(),
+                rotation: v(),
+            },
+            "rotation_ik" => BoneAnim::RotationIk {
+                target: v(),
+                rotation: v(),
+            },
+            "arm_ik" => BoneAnim::ArmIk {
+                target: v(),
+                rotation: v(),
+            },
+            "leg_ik" => BoneAnim::LegIk {
+                position: v(),
+                target: v(),
+            },
+            _ => return None,
+        };
+        Some(anim)
+    }
+}
+
+impl<'a> fmt::Display for Motion<'a> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "motion {}", self.frames)?;
+        for (bone, anim) in &self.anims {
+            let anim = match anim {
+                Some(anim) => anim,
+                None => {
+                    writeln!(f, "bone {} none", bone.0)?;
+                    continue;
+                }
+            };
+            writeln!(f, "bone {} {}", bone.0, anim.kind())?;
+            for (component, vec) in anim.components() {
+                for &axis in Axis::ALL.iter() {
+                    write!(f, "  {}.{} ", component, axis)?;
+                    write_curve(f, "  ", axis.get(vec))?;
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+impl<'a> Motion<'a> {
+    ///Reads a motion written by its `Display` impl, borrowing the bone names from `text`
+    pub fn from_text(text: &'a str) -> Result<Self, TextError> {
+        let mut lines = Lines::new(text);
+        let (i, words) = lines.expect("motion", "a `motion` line")?;
+        let frames = match &words[..] {
+            [_, frames] => number(i, frames)?,
+            _ => return Err(TextError::Expected(i, "a frame count")),
+        };
+        let mut anims = BTreeMap::new();
+        while lines.peek().is_some() {
+            let (i, words) = lines.expect("bone", "a `bone` line")?;
+            let (name, kind) = match &words[..] {
+                [_, name, kind] => (*name, *kind),
+                _ => return Err(TextError::Expected(i, "a bone name and animation type")),
+            };
+            let mut anim = match kind {
+                "none" => None,
+                _ => Some(
+                    BoneAnim::empty(kind)
+                        .ok_or_else(|| TextError::UnknownAnim(i, kind.to_string()))?,
+                ),
+            };
+            while let Some((i, words)) = lines.peek().cloned() {
+                if words[0] == "bone" {
+                    break;
+                }
+                lines.next();
+                let path = words[0];
+                let (component, axis) = match path.rfind('.') {
+                    Some(dot) => (&path[..dot], &path[dot + 1..]),
+                    None => return Err(TextError::Expected(i, "a `component.axis` channel")),
+                };
+                let component: Component =
+                    component.parse().map_err(|e| TextError::Channel(i, e))?;
+                let axis: Axis = axis.parse().map_err(|e| TextError::Channel(i, e))?;
+                let data = lines.curve(i, &words[1..])?;
+                let vec = anim
+                    .as_mut()
+                    .and_then(|a| a.component_mut(component))
+                    .ok_or_else(|| TextError::NoChannel(i, path.to_string()))?;
+                *axis.get_mut(vec) = data;
+            }
+            anims.insert(Bone(Cow::Borrowed(name)), anim);
+        }
+        Ok(Motion { frames, anims })
+    }
+}
+
+impl fmt::Display for RawMotion {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "raw {}", self.frames)?;
+        write!(f, "bones")?;
+        for bone in &self.bones {
+            write!(f, " {}", bone)?;
+        }
+        writeln!(f)?;
+        for set in &self.sets {
+            write!(f, "set ")?;
+            write_curve(f, "", set)?;
+        }
+        Ok(())
+    }
+}
+
+impl RawMotion {
+    ///Reads a raw motion written by its `Display` impl.
+    ///
+    ///Like [`RawMotion::read`], the padding set the game expects at the end is left out and
+    ///added back on write.
+    pub fn from_text(text: &str) -> Result<Self, TextError> {
+        let mut lines = Lines::new(text);
+        let (i, words) = lines.expect("raw", "a `raw` line")?;
+        let frames = match &words[..] {
+            [_, frames] => number(i, frames)?,
+            _ => return Err(TextError::Expected(i, "a frame count")),
+        };
+        let (i, words) = lines.expect("bones", "a `bones` line")?;
+        let bones = words[1..]
+            .iter()
+            .map(|x| number(i, x))
+            .collect::<Result<_, _>>()?;
+        let mut sets = vec![];
+        while lines.peek().is_some() {
+            let (i, words) = lines.expect("set", "a `set` line")?;
+            sets.push(lines.curve(i, &words[1..])?);
+        }
+        Ok(RawMotion {
+            sets,
+            bones,
+            frames,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn keys() -> FrameData {
+        FrameData::CatmulRom(vec![
+            Keyframe {
+                frame: 0,
+                value: -0.,
+                interpolation: (),
+            },
+            Keyframe {
+                frame: 30,
+                value: 0.1 + 0.2,
+                interpolation: (),
+            },
+        ])
+    }
+
+    #[test]
+    fn motion_roundtrip() -> anyhow::Result<()> {
+        let hermite = FrameData::Hermite(vec![Keyframe {
+            frame: 12,
+            value: 1e-7,
+            interpolation: -3.5,
+        }]);
+        let mut anims = BTreeMap::new();
+        anims.insert(
+            Bone("n_hara_cp".into()),
+            Some(BoneAnim::PositionRotation {
+                position: (FrameData::None, FrameData::Pose(1.), keys()),
+                rotation: (hermite, FrameData::Pose(0.25), FrameData::None),
+            }),
+        );
+        anims.insert(
+            Bone("cl_momo_l".into()),
+            Some(BoneAnim::LegIk {
+                position: (keys(), keys(), keys()),
+                target: (FrameData::Pose(-1.), FrameData::None, FrameData::None),
+            }),
+        );
+        anims.insert(Bone("kl_kubi".into()), None);
+        let mot = Motion { frames: 31, anims };
+
+        let text = mot.to_string();
+        assert!(text.contains("  position.z catmull_rom\n    0 -0\n    30 0.3"));
+        assert_eq!(Motion::from_text(&text)?, mot);
+        Ok(())
+    }
+
+    #[test]
+    fn raw_roundtrip() -> anyhow::Result<()> {
+        let raw = RawMotion {
+            sets: vec![FrameData::Pose(2.), keys(), FrameData::None],
+            bones: vec![0, 17, 3],
+            frames: 31,
+        };
+        let text = raw.to_string();
+        assert!(text.starts_with("raw 31\nbones 0 17 3\nset pose 2\n"));
+        assert_eq!(RawMotion::from_text(&text)?, raw);
+        Ok(())
+    }
+
+    #[test]
+    fn errors_name_lines() {
+        let text = "motion 10\nbone kl_kubi rotation\n  position.x pose 1\n";
+        assert_eq!(
+            Motion::from_text(text),
+            Err(TextError::NoChannel(3, "position.x".to_string()))
+        );
+        let text = "motion 10\n\nbone kl_kubi rotation\n  rotation.x catmull_rom\n    0 zero\n";
+        assert_eq!(
+            Motion::from_text(text),
+            Err(TextError::BadNumber(5, "zero".to_string()))
+        );
+    }
+
+    #[test]
+    fn pv_roundtrip() -> anyhow::Result<()> {
+        let input = include_bytes!("../assets/mot_PV001.bin");
+        for raw in RawMotion::read(input)? {
+            assert_eq!(RawMotion::from_text(&raw.to_string())?, raw);
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,291 @@
+use super::*;
+
+use core::ops::Range;
+
+impl FrameData {
+    ///Cuts out the frames `start..=end`, rebased so that `start` lands on frame 0.
+    ///
+    ///Cut points that fall between keys get a new key evaluated from the curve.
+    pub fn trimmed(&self, start: u16, end: u16) -> Self {
+        let end = end.max(start);
+        match self {
+            FrameData::None | FrameData::Pose(_) => self.clone(),
+            FrameData::CatmulRom(v) => {
+                let boundary = |frame: u16| Keyframe {
+                    frame,
+                    value: self.evaluate(frame as f32).unwrap_or_default(),
+                    interpolation: (),
+                };
+                FrameData::CatmulRom(trim_keys(v, start, end, boundary))
+            }
+            FrameData::Hermite(v) => {
+                let boundary = |frame: u16| Keyframe {
+                    frame,
+                    value: self.evaluate(frame as f32).unwrap_or_default(),
+                    interpolation: self.slope(frame as f32).unwrap_or_default(),
+                };
+                FrameData::Hermite(trim_keys(v, start, end, boundary))
+            }
+        }
+    }
+
+    ///Moves every key by `delta` frames. Keys pushed before frame 0 are cut off
+    pub fn shifted(&self, delta: i32) -> Self {
+        let last = match self.key_frames().last() {
+            Some(&last) => last as i32,
+            None => return self.clone(),
+        };
+        //Every key lands past the last representable frame
+        if delta > u16::MAX as i32 {
+            return match self {
+                FrameData::CatmulRom(_) => FrameData::CatmulRom(vec![]),
+                FrameData::Hermite(_) => FrameData::Hermite(vec![]),
+                x => x.clone(),
+            };
+        }
+        let delta = delta.max(-(u16::MAX as i32));
+        let start = (-delta).max(0);
+        let base = delta + start;
+        //Keys pushed past the last representable frame are cut off as well
+        let end = last.min(u16::MAX as i32 - base).max(start);
+        let mut data = self.trimmed(start as u16, end as u16);
+        match &mut data {
+            FrameData::CatmulRom(v) => v.iter_mut().for_each(|k| k.frame += base as u16),
+            FrameData::Hermite(v) => v.iter_mut().for_each(|k| k.frame += base as u16),
+            _ => {}
+        }
+        data
+    }
+
+    ///Plays the frames `0..=last` backwards
+    pub fn reversed(&self, last: u16) -> Self {
+        match self.trimmed(0, last) {
+            FrameData::CatmulRom(mut v) => {
+                v.reverse();
+                v.iter_mut().for_each(|k| k.frame = last - k.frame);
+                FrameData::CatmulRom(v)
+            }
+            FrameData::Hermite(mut v) => {
+                v.reverse();
+                v.iter_mut().for_each(|k| {
+                    k.frame = last - k.frame;
+                    k.interpolation = -k.interpolation;
+                });
+                FrameData::Hermite(v)
+            }
+            x => x,
+        }
+    }
+
+    ///Plays `self` up to frame `at`, then `other` starting from frame `at`
+    pub fn concat(&self, other: &FrameData, at: u16) -> Self {
+        if at == 0 {
+            return other.clone();
+        }
+        match (self, other) {
+            (FrameData::None, FrameData::None) => return FrameData::None,
+            (FrameData::Pose(a), FrameData::Pose(b)) if a == b => return self.clone(),
+            _ => {}
+        }
+        let other_last = other.key_frames().last().copied().unwrap_or_default();
+        let left = self.keyed_over(0, at - 1);
+        let right = other.keyed_over(0, other_last).shifted(at as i32);
+        match (left, right) {
+            (FrameData::CatmulRom(mut l), FrameData::CatmulRom(r)) => {
+                l.extend(r);
+                FrameData::CatmulRom(l)
+            }
+            (l, r) => match (l.into_hermite(), r.into_hermite()) {
+                (FrameData::Hermite(mut l), FrameData::Hermite(r)) => {
+                    l.extend(r);
+                    FrameData::Hermite(l)
+                }
+                _ => unreachable!("`keyed_over` always returns keyed data"),
+            },
+        }
+    }
+
+    ///Returns keyed data that covers `start..=end`, turning constants into keys at both ends.
+    ///Missing data is treated as a constant 0
+    fn keyed_over(&self, start: u16, end: u16) -> Self {
+        match self {
+            FrameData::None => FrameData::Pose(0.).keyed_over(start, end),
+            FrameData::Pose(value) => {
+                let key = |frame| Keyframe {
+                    frame,
+                    value: *value,
+                    interpolation: (),
+                };
+                let mut keys = vec![key(start)];
+                if end > start {
+                    keys.push(key(end));
+                }
+                FrameData::CatmulRom(keys)
+            }
+            x => x.trimmed(start, end).shifted(start as i32),
+        }
+    }
+}
+
+fn trim_keys<I, F>(keys: &[Keyframe<I>], start: u16, end: u16, boundary: F) -> Vec<Keyframe<I>>
+where
+    I: Copy,
+    F: Fn(u16) -> Keyframe<I>,
+{
+    let mut trimmed = Vec::with_capacity(keys.len() + 2);
+    if !keys.iter().any(|k| k.frame == start) {
+        trimmed.push(boundary(start));
+    }
+    trimmed.extend(
+        keys.iter()
+            .filter(|k| k.frame >= start && k.frame <= end)
+            .copied(),
+    );
+    if end != start && !keys.iter().any(|k| k.frame == end) {
+        trimmed.push(boundary(end));
+    }
+    for key in &mut trimmed {
+        key.frame -= start;
+    }
+    trimmed
+}
+
+impl<'a> Motion<'a> {
+    pub fn frames(&self) -> u16 {
+        self.frames
+    }
+
+    ///Copies the frames in `range` into a new motion starting at frame 0
+    pub fn trim(&self, range: Range<u16>) -> Self {
+        let frames = range.end.saturating_sub(range.start);
+        let last = range.start + frames.saturating_sub(1);
+        let mut mot = self.clone();
+        for (_, data) in mot.channels_mut() {
+            *data = data.trimmed(range.start, last);
+        }
+        mot.frames = frames;
+        mot
+    }
+
+    ///Moves the whole motion by `delta` frames, cutting off anything pushed before frame 0
+    pub fn offset(&mut self, delta: i32) {
+        for (_, data) in self.channels_mut() {
+            *data = data.shifted(delta);
+        }
+        self.frames = (self.frames as i32)
+            .saturating_add(delta)
+            .clamp(0, u16::MAX as i32) as u16;
+    }
+
+    ///Appends `other` after the last frame of `self`.
+    ///
+    ///Bones only animated by one of the motions keep that animation, held past its end.
+    ///Bones whose animation types differ keep the animation of `self`.
+    pub fn append(&mut self, other: &Motion<'a>) {
+        let at = self.frames;
+        for (bone, anim) in &other.anims {
+            let anim = match anim {
+                Some(anim) => anim,
+                None => continue,
+            };
+            match self.anims.get_mut(bone) {
+                Some(Some(current)) => {
+                    if let Some(joined) = current.zip_with(anim, |_, _, l, r| l.concat(r, at)) {
+                        *current = joined;
+                    }
+                }
+                _ => {
+                    let shifted = anim.map(|_, _, data| data.shifted(at as i32));
+                    self.anims.insert(bone.clone(), Some(shifted));
+                }
+            }
+        }
+        self.frames = self.frames.saturating_add(other.frames);
+    }
+
+    ///Plays the motion backwards
+    pub fn reverse(&mut self) {
+        let last = self.frames.saturating_sub(1);
+        for (_, data) in self.channels_mut() {
+            *data = data.reversed(last);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn ramp() -> FrameData {
+        let mut data = FrameData::None;
+        data.insert_key(0, 0.);
+        data.insert_key(10, 10.);
+        data.insert_key(20, 20.);
+        data
+    }
+
+    fn motion(data: FrameData) -> Motion<'static> {
+        let mut anims = BTreeMap::new();
+        let rot = (data, FrameData::Pose(1.), FrameData::None);
+        anims.insert(Bone("kl_kubi".into()), Some(BoneAnim::Rotation(rot)));
+        Motion { frames: 21, anims }
+    }
+
+    #[test]
+    fn trim_rekeys_boundaries() {
+        let data = ramp().trimmed(5, 15);
+        assert_eq!(data.key_frames(), vec![0, 5, 10]);
+        assert_eq!(data.evaluate(0.), Some(5.));
+        assert_eq!(data.evaluate(10.), Some(15.));
+
+        let mot = motion(ramp()).trim(5..16);
+        assert_eq!(mot.frames(), 11);
+    }
+
+    #[test]
+    fn shift_cuts_negative_keys() {
+        let data = ramp().shifted(-5);
+        assert_eq!(data.key_frames(), vec![0, 5, 15]);
+        assert_eq!(data.evaluate(0.), Some(5.));
+        assert_eq!(ramp().shifted(5).key_frames(), vec![5, 15, 25]);
+    }
+
+    #[test]
+    fn shift_extreme_deltas() {
+        let data = ramp().shifted(i32::MIN);
+        assert_eq!(data.key_frames(), vec![0]);
+        assert_eq!(data.evaluate(0.), Some(20.));
+        assert_eq!(ramp().shifted(u16::MAX as i32).key_frames(), vec![u16::MAX]);
+        assert_eq!(ramp().shifted(70000), FrameData::CatmulRom(vec![]));
+        assert_eq!(ramp().shifted(i32::MAX), FrameData::CatmulRom(vec![]));
+
+        let mut mot = motion(ramp());
+        mot.offset(i32::MAX);
+        assert_eq!(mot.frames(), u16::MAX);
+        let mut mot = motion(ramp());
+        mot.offset(i32::MIN);
+        assert_eq!(mot.frames(), 0);
+    }
+
+    #[test]
+    fn reverse_mirrors_time() {
+        let data = ramp().reversed(20);
+        assert_eq!(data.key_frames(), vec![0, 10, 20]);
+        assert_eq!(data.evaluate(0.), Some(20.));
+        assert_eq!(data.evaluate(20.), Some(0.));
+    }
+
+    #[test]
+    fn append_joins_curves() {
+        let mut mot = motion(ramp());
+        mot.append(&motion(FrameData::Pose(3.)));
+        assert_eq!(mot.frames(), 42);
+        let path = channel::ChannelPath::parse("kl_kubi.rotation.x").unwrap();
+        let data = mot.get(&path).unwrap();
+        assert_eq!(data.evaluate(20.), Some(20.));
+        assert_eq!(data.evaluate(21.), Some(3.));
+        assert_eq!(data.evaluate(41.), Some(3.));
+        let path = channel::ChannelPath::parse("kl_kubi.rotation.y").unwrap();
+        assert_eq!(mot.get(&path), Some(&FrameData::Pose(1.)));
+    }
+}
@@ -0,0 +1,196 @@
+use super::*;
+use crate::math::{Quat, Transform, Vector3};
+use crate::pose::local_transform;
+use crate::skeleton::Skeleton;
+
+use core::fmt::Write;
+
+fn vector([x, y, z]: Vector3) -> String {
+    format!("({}, {}, {})", x, y, z)
+}
+
+fn matrix(t: Transform) -> String {
+    let m = t.to_matrix();
+    let rows: Vec<String> = m
+        .chunks(4)
+        .map(|r| format!("({}, {}, {}, {})", r[0], r[1], r[2], r[3]))
+        .collect();
+    format!("( {} )", rows.join(", "))
+}
+
+fn list(items: impl IntoIterator<Item = String>) -> String {
+    format!("[{}]", items.into_iter().collect::<Vec<_>>().join(", "))
+}
+
+impl<'a> Motion<'a> {
+    ///Exports the motion on `skeleton` as a text USD layer holding a `Skeleton` with a
+    ///`SkelAnimation`, sampled on every frame at `fps`.
+    ///
+    ///IK bones are baked with [`Motion::bake_ik`] first. `gblctr` and `kg_ya_ex` become the two
+    ///topmost joints.
+    pub fn to_usda(&self, skeleton: &Skeleton, fps: f32, tolerance: f32) -> String {
+        let fk = skeleton.without_ik();
+        let mot = self.bake_ik(skeleton, tolerance);
+
+        //USD wants parents listed before their children
+        let mut order = vec![];
+        let mut stack: Vec<usize> = (0..fk.bones.len())
+            .filter(|&i| fk.bones[i].parent.is_none())
+            .rev()
+            .collect();
+        while let Some(i) = stack.pop() {
+            order.push(i);
+            let mut children: Vec<usize> = fk.children(i).collect();
+            children.reverse();
+            stack.extend(children);
+        }
+        let mut paths = vec!["gblctr".to_string(), "gblctr/kg_ya_ex".to_string()];
+        let mut index = vec![0; fk.bones.len()];
+        for &i in &order {
+            let bone = &fk.bones[i];
+            let parent = match bone.parent {
+                Some(p) => &paths[index[p]],
+                None => &paths[1],
+            };
+            let path = format!("{}/{}", parent, bone.name);
+            index[i] = paths.len();
+            paths.push(path);
+        }
+
+        let rest: Vec<Transform> = [Transform::IDENTITY; 2]
+            .iter()
+            .copied()
+            .chain(
+                order
+                    .iter()
+                    .map(|&i| Transform::new(fk.bones[i].rest(), Quat::IDENTITY)),
+            )
+            .collect();
+        let mut world = rest.clone();
+        world[1] = world[0] * rest[1];
+        for (n, &i) in order.iter().enumerate() {
+            let parent = fk.bones[i].parent.map_or(1, |p| index[p]);
+            world[n + 2] = world[parent] * rest[n + 2];
+        }
+
+        let frames = mot.frames.max(1);
+        let mut translations = String::new();
+        let mut rotations = String::new();
+        for f in 0..frames {
+            let frame = f as f32;
+            let mut locals = vec![
+                local_transform(mot.anim("gblctr"), [0.; 3], frame).0,
+                local_transform(mot.anim("kg_ya_ex"), [0.; 3], frame).0,
+            ];
+            for &i in &order {
+                let bone = &fk.bones[i];
+                locals.push(local_transform(mot.anim(&bone.name), bone.rest(), frame).0);
+            }
+            let t = list(locals.iter().map(|t| vector(t.translation)));
+            let r = list(locals.iter().map(|t| {
+                let Quat { x, y, z, w } = t.rotation;
+                format!("({}, {}, {}, {})", w, x, y, z)
+            }));
+            let _ = writeln!(translations, "                {}: {},", f, t);
+            let _ = writeln!(rotations, "                {}: {},", f, r);
+        }
+
+        let joints = list(paths.iter().map(|p| format!("\"{}\"", p)));
+        let mut out = String::new();
+        let _ = write!(
+            out,
+            r#"#usda 1.0
+(
+    defaultPrim = "Motion"
+    startTimeCode = 0
+    endTimeCode = {end}
+    timeCodesPerSecond = {fps}
+    framesPerSecond = {fps}
+    metersPerUnit = 1
+    upAxis = "Y"
+)
+
+def SkelRoot "Motion"
+{{
+    def Skeleton "Skeleton" (
+        prepend apiSchemas = ["SkelBindingAPI"]
+    )
+    {{
+        uniform token[] joints = {joints}
+        uniform matrix4d[] bindTransforms = {bind}
+        uniform matrix4d[] restTransforms = {rest}
+        rel skel:animationSource = </Motion/Skeleton/Animation>
+
+        def SkelAnimation "Animation"
+        {{
+            uniform token[] joints = {joints}
+            float3[] translations.timeSamples = {{
+{translations}            }}
+            quatf[] rotations.timeSamples = {{
+{rotations}            }}
+            half3[] scales = {scales}
+        }}
+    }}
+}}
+"#,
+            end = frames - 1,
+            fps = fps,
+            joints = joints,
+            bind = list(world.iter().map(|&t| matrix(t))),
+            rest = list(rest.iter().map(|&t| matrix(t))),
+            translations = translations,
+            rotations = rotations,
+            scales = list(paths.iter().map(|_| vector([1.; 3]))),
+        );
+        out
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use diva_db::bone::BoneType;
+
+    #[test]
+    fn joints_and_samples() {
+        let skeleton = Skeleton::test(&[
+            ("kl_kubi", BoneType::Rotation, Some(1), &[[0., 0.5, 0.]]),
+            ("n_hara_cp", BoneType::Type3, None, &[[0., 1., 0.]]),
+        ]);
+        let mut anims = BTreeMap::new();
+        anims.insert(
+            Bone("n_hara_cp".into()),
+            Some(BoneAnim::PositionRotation {
+                position: (
+                    FrameData::Pose(0.),
+                    FrameData::Pose(1.),
+                    FrameData::CatmulRom(vec![
+                        Keyframe {
+                            frame: 0,
+                            value: 0.,
+                            interpolation: (),
+                        },
+                        Keyframe {
+                            frame: 2,
+                            value: 2.,
+                            interpolation: (),
+                        },
+                    ]),
+                ),
+                rotation: (FrameData::None, FrameData::None, FrameData::None),
+            }),
+        );
+        let mot = Motion { frames: 3, anims };
+        let usda = mot.to_usda(&skeleton, 60., 1e-5);
+        assert!(usda.starts_with("#usda 1.0\n"));
+        assert!(usda.contains(
+            r#"joints = ["gblctr", "gblctr/kg_ya_ex", "gblctr/kg_ya_ex/n_hara_cp", "gblctr/kg_ya_ex/n_hara_cp/kl_kubi"]"#
+        ));
+        assert!(usda.contains("endTimeCode = 2\n"));
+        assert!(usda.contains("2: [(0, 0, 0), (0, 0, 0), (0, 1, 2), (0, 0.5, 0)],"));
+        //The neck sits on the hips at rest
+        assert!(usda.contains(
+            "(0, 1, 0, 1) ), ( (1, 0, 0, 0), (0, 1, 0, 0), (0, 0, 1, 0), (0, 1.5, 0, 1) )]"
+        ));
+    }
+}
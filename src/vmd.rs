@@ -0,0 +1,467 @@
+use super::*;
+use crate::blend::{fit, sample, to_euler};
+use crate::math::{self, Quat, Vector3};
+use crate::retarget::BoneMap;
+use crate::skeleton::Skeleton;
+use diva_db::bone::BoneType;
+use encoding_rs::SHIFT_JIS;
+
+use core::convert::TryFrom;
+
+#[derive(Copy, Clone, PartialEq, Eq, PartialOrd, Ord, Debug, Error)]
+pub enum VmdError {
+    #[error("Not a VMD file")]
+    NotVmd,
+    #[error("File ended early")]
+    UnexpectedEnd,
+    #[error("Key on frame {0} is too late to fit in a motion")]
+    TooLong(u32),
+}
+
+///Keys of VMD files are at 30 frames per second
+pub const VMD_FPS: f32 = 30.;
+
+const MAGIC: &[u8] = b"Vocaloid Motion Data 0002";
+
+///Interpolation parameters for a straight line
+pub const LINEAR: [u8; 4] = [20, 20, 107, 107];
+
+#[derive(Clone, PartialEq, Debug)]
+pub struct VmdBoneKey {
+    pub name: String,
+    pub frame: u32,
+    ///Offset from the bone's rest position, in MMD's left handed space
+    pub position: Vector3,
+    pub rotation: Quat,
+    ///Bézier control points `x1, y1, x2, y2` in 0 to 127 for the X, Y and Z position and the
+    ///rotation, easing into this key from the one before
+    pub curves: [[u8; 4]; 4],
+}
+
+///The bone motion of a MikuMikuDance motion file
+#[derive(Clone, PartialEq, Debug, Default)]
+pub struct Vmd {
+    pub model: String,
+    pub bones: Vec<VmdBoneKey>,
+}
+
+fn decode(bytes: &[u8]) -> String {
+    let end = bytes.iter().position(|&x| x == 0).unwrap_or(bytes.len());
+    SHIFT_JIS
+        .decode_without_bom_handling(&bytes[..end])
+        .0
+        .into_owned()
+}
+
+///Encodes as Shift-JIS padded with zeroes, cutting off whole characters that don't fit
+fn encode(text: &str, len: usize) -> Vec<u8> {
+    let mut out = Vec::with_capacity(len);
+    let mut buf = [0; 4];
+    for c in text.chars() {
+        let (bytes, _, _) = SHIFT_JIS.encode(c.encode_utf8(&mut buf));
+        if out.len() + bytes.len() > len {
+            break;
+        }
+        out.extend_from_slice(&bytes);
+    }
+    out.resize(len, 0);
+    out
+}
+
+struct Reader<'a>(&'a [u8]);
+
+impl<'a> Reader<'a> {
+    fn take(&mut self, len: usize) -> Result<&'a [u8], VmdError> {
+        if self.0.len() < len {
+            return Err(VmdError::UnexpectedEnd);
+        }
+        let (x, rest) = self.0.split_at(len);
+        self.0 = rest;
+        Ok(x)
+    }
+
+    fn u32(&mut self) -> Result<u32, VmdError> {
+        let b = self.take(4)?;
+        Ok(u32::from_le_bytes([b[0], b[1], b[2], b[3]]))
+    }
+
+    fn f32(&mut self) -> Result<f32, VmdError> {
+        Ok(f32::from_bits(self.u32()?))
+    }
+}
+
+impl Vmd {
+    ///Reads the bone keys of a `.vmd` file, ignoring morphs, cameras and lights
+    pub fn read(data: &[u8]) -> Result<Self, VmdError> {
+        let mut r = Reader(data);
+        if !r.take(30)?.starts_with(MAGIC) {
+            return Err(VmdError::NotVmd);
+        }
+        let model = decode(r.take(20)?);
+        let count = r.u32()?;
+        let mut bones = Vec::with_capacity(count.min(1 << 20) as usize);
+        for _ in 0..count {
+            let name = decode(r.take(15)?);
+            let frame = r.u32()?;
+            let position = [r.f32()?, r.f32()?, r.f32()?];
+            let rotation = Quat {
+                x: r.f32()?,
+                y: r.f32()?,
+                z: r.f32()?,
+                w: r.f32()?,
+            };
+            let raw = r.take(64)?;
+            let mut curves = [[0; 4]; 4];
+            for (i, curve) in curves.iter_mut().enumerate() {
+                for (j, x) in curve.iter_mut().enumerate() {
+                    *x = raw[j * 4 + i];
+                }
+            }
+            bones.push(VmdBoneKey {
+                name,
+                frame,
+                position,
+                rotation,
+                curves,
+            });
+        }
+        Ok(Self { model, bones })
+    }
+
+    pub fn write(&self) -> Vec<u8> {
+        let mut out = Vec::with_capacity(54 + self.bones.len() * 111 + 16);
+        let mut header = MAGIC.to_vec();
+        header.resize(30, 0);
+        out.extend(header);
+        out.extend(encode(&self.model, 20));
+        out.extend_from_slice(&(self.bones.len() as u32).to_le_bytes());
+        for key in &self.bones {
+            out.extend(encode(&key.name, 15));
+            out.extend_from_slice(&key.frame.to_le_bytes());
+            let Quat { x, y, z, w } = key.rotation;
+            for v in key.position.iter().chain(&[x, y, z, w]) {
+                out.extend_from_slice(&v.to_le_bytes());
+            }
+            let mut row = [0; 16];
+            for (i, curve) in key.curves.iter().enumerate() {
+                for (j, &x) in curve.iter().enumerate() {
+                    row[j * 4 + i] = x;
+                }
+            }
+            //MMD repeats the parameters shifted by one byte on each following row
+            for shift in 0..4 {
+                out.extend_from_slice(&row[shift..]);
+                out.extend((0..shift).map(|i| if i == 0 { 1 } else { 0 }));
+            }
+        }
+        //No morph, camera, light or shadow keys
+        for _ in 0..4 {
+            out.extend_from_slice(&0u32.to_le_bytes());
+        }
+        out
+    }
+}
+
+///Progress along a VMD Bézier curve at `t`, both between 0 and 1
+pub fn bezier([x1, y1, x2, y2]: [u8; 4], t: f32) -> f32 {
+    let (x1, y1, x2, y2) = (
+        x1 as f32 / 127.,
+        y1 as f32 / 127.,
+        x2 as f32 / 127.,
+        y2 as f32 / 127.,
+    );
+    let curve = |a: f32, b: f32, s: f32| {
+        let r = 1. - s;
+        3. * r * r * s * a + 3. * r * s * s * b + s * s * s
+    };
+    //x is monotonic in s, so bisect for the s that lands on t
+    let (mut lo, mut hi) = (0f32, 1f32);
+    for _ in 0..24 {
+        let mid = (lo + hi) / 2.;
+        if curve(x1, x2, mid) < t {
+            lo = mid;
+        } else {
+            hi = mid;
+        }
+    }
+    curve(y1, y2, (lo + hi) / 2.)
+}
+
+///Converts between MMD's left handed space and the game's right handed one
+fn flip_position([x, y, z]: Vector3) -> Vector3 {
+    [x, y, -z]
+}
+
+fn flip_rotation(q: Quat) -> Quat {
+    Quat {
+        x: -q.x,
+        y: -q.y,
+        ..q
+    }
+}
+
+///Samples the keys of one bone, sorted by frame, at VMD frame `time`
+fn sample_keys(keys: &[&VmdBoneKey], time: f32) -> (Vector3, Quat) {
+    let next = keys.iter().position(|k| k.frame as f32 > time);
+    let (a, b) = match next {
+        Some(0) => return (keys[0].position, keys[0].rotation),
+        None => {
+            let last = keys[keys.len() - 1];
+            return (last.position, last.rotation);
+        }
+        Some(i) => (keys[i - 1], keys[i]),
+    };
+    let t = (time - a.frame as f32) / (b.frame - a.frame) as f32;
+    let mut position = [0.; 3];
+    for (i, x) in position.iter_mut().enumerate() {
+        let t = bezier(b.curves[i], t);
+        *x = a.position[i] + (b.position[i] - a.position[i]) * t;
+    }
+    let rotation = a.rotation.slerp(b.rotation, bezier(b.curves[3], t));
+    (position, rotation)
+}
+
+impl BoneMap {
+    ///Maps the standard PMX bone names onto the joints of [`Skeleton::without_ik`].
+    ///
+    ///MMD's leg IK bones (`足ＩＫ`) aren't mapped, so motions driving the legs through IK need
+    ///their IK baked into the leg bones in MMD first.
+    pub fn pmx() -> Self {
+        let mut map: BoneMap = [
+            ("センター", "n_hara_cp"),
+            ("上半身", "j_mune_wj"),
+            ("下半身", "kl_kosi_y"),
+            ("首", "kl_kubi"),
+            ("頭", "j_kao_wj"),
+        ]
+        .iter()
+        .copied()
+        .collect();
+        for &(side, l) in [("左", "l"), ("右", "r")].iter() {
+            let bones = [
+                ("肩", "kl_waki_{}_wj"),
+                ("腕", "j_kata_{}_wj_cu"),
+                ("ひじ", "j_ude_{}_wj"),
+                ("手首", "kl_te_{}_wj"),
+                ("足", "j_momo_{}_wj"),
+                ("ひざ", "j_sune_{}_wj"),
+                ("足首", "kl_asi_{}_wj_co"),
+                ("つま先", "kl_toe_{}_wj"),
+                ("親指０", "nl_oya_{}_wj"),
+                ("親指１", "nl_oya_b_{}_wj"),
+                ("親指２", "nl_oya_c_{}_wj"),
+            ];
+            for &(mmd, diva) in bones.iter() {
+                map.insert(&format!("{}{}", side, mmd), &diva.replace("{}", l));
+            }
+            let fingers = [
+                ("人指", "hito"),
+                ("中指", "naka"),
+                ("薬指", "kusu"),
+                ("小指", "ko"),
+            ];
+            for &(mmd, diva) in fingers.iter() {
+                for (n, part) in ["１", "２", "３"].iter().zip(&["", "_b", "_c"]) {
+                    map.insert(
+                        &format!("{}{}{}", side, mmd, n),
+                        &format!("nl_{}{}_{}_wj", diva, part, l),
+                    );
+                }
+            }
+        }
+        map
+    }
+}
+
+impl<'a> Motion<'a> {
+    ///Imports the bone keys of `vmd` onto `skeleton`, renaming bones through `map` and
+    ///multiplying positions by `scale`.
+    ///
+    ///The keys are sampled `fps` times per second and fitted to `tolerance`. The model should be
+    ///in the same rest pose as the skeleton. IK chains are rebuilt with [`Motion::to_ik`].
+    pub fn from_vmd(
+        vmd: &Vmd,
+        map: &BoneMap,
+        skeleton: &Skeleton,
+        fps: f32,
+        scale: f32,
+        tolerance: f32,
+    ) -> Result<Self, VmdError> {
+        let fk = skeleton.without_ik();
+        let mut tracks: BTreeMap<&str, Vec<&VmdBoneKey>> = BTreeMap::new();
+        for key in &vmd.bones {
+            tracks.entry(&key.name[..]).or_default().push(key);
+        }
+        let last = vmd.bones.iter().map(|k| k.frame).max().unwrap_or_default();
+        let frames = u16::try_from((last as f64 * fps as f64 / VMD_FPS as f64).round() as i64)
+            .ok()
+            .and_then(|x| x.checked_add(1))
+            .ok_or(VmdError::TooLong(last))?;
+
+        let mut anims = BTreeMap::new();
+        for (name, keys) in tracks.iter_mut() {
+            let bone = match fk.get(map.get(name)) {
+                Some(bone) => bone,
+                None => continue,
+            };
+            keys.sort_by_key(|k| k.frame);
+            let samples: Vec<(Vector3, Quat)> = (0..frames)
+                .map(|f| sample_keys(keys, f as f32 * VMD_FPS / fps))
+                .collect();
+            let rotations: Vec<Quat> = samples.iter().map(|s| flip_rotation(s.1)).collect();
+            let rotation = fit(0, &to_euler(&rotations), tolerance);
+            let positions = || {
+                let positions: Vec<Vector3> = samples
+                    .iter()
+                    .map(|s| math::add(bone.rest(), math::scale(flip_position(s.0), scale)))
+                    .collect();
+                fit(0, &positions, tolerance)
+            };
+            let anim = match bone.mode {
+                BoneType::Position => BoneAnim::Position(positions()),
+                BoneType::Type3 => BoneAnim::PositionRotation {
+                    position: positions(),
+                    rotation,
+                },
+                _ => BoneAnim::Rotation(rotation),
+            };
+            anims.insert(Bone(Cow::Owned(bone.name.to_string())), Some(anim));
+        }
+        let mot = Motion { frames, anims };
+        Ok(mot.to_ik(skeleton, tolerance))
+    }
+
+    ///Exports the motion on `skeleton` as VMD bone keys for `model`, renaming bones through
+    ///`map` and dividing positions by `scale`.
+    ///
+    ///IK bones are baked with [`Motion::bake_ik`] first, and every bone gets a linear key on
+    ///every VMD frame. Bones that are still neither positions nor rotations afterwards, `Unk`
+    ///bones and IK bones without a chain in `skeleton`, have no VMD equivalent and are skipped.
+    pub fn to_vmd(
+        &self,
+        skeleton: &Skeleton,
+        map: &BoneMap,
+        model: &str,
+        fps: f32,
+        scale: f32,
+        tolerance: f32,
+    ) -> Vmd {
+        let fk = skeleton.without_ik();
+        let mot = self.bake_ik(skeleton, tolerance);
+        let last = (mot.frames.max(1) - 1) as f32 * VMD_FPS / fps;
+        let mut bones = vec![];
+        for bone in &fk.bones {
+            let (position, rotation) = match mot.anim(&bone.name) {
+                Some(BoneAnim::Position(p)) => (Some(p), None),
+                Some(BoneAnim::Rotation(r)) => (None, Some(r)),
+                Some(BoneAnim::PositionRotation { position, rotation }) => {
+                    (Some(position), Some(rotation))
+                }
+                _ => continue,
+            };
+            for frame in 0..=last.round() as u32 {
+                let f = frame as f32 * fps / VMD_FPS;
+                let position = position.map_or(bone.rest(), |p| sample(p, f));
+                let offset = math::scale(math::sub(position, bone.rest()), 1. / scale);
+                let rotation = rotation.map_or(Quat::IDENTITY, |r| Quat::from_euler(sample(r, f)));
+                bones.push(VmdBoneKey {
+                    name: map.get(&bone.name).to_string(),
+                    frame,
+                    position: flip_position(offset),
+                    rotation: flip_rotation(rotation),
+                    curves: [LINEAR; 4],
+                });
+            }
+        }
+        Vmd {
+            model: model.to_string(),
+            bones,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::channel::ChannelPath;
+
+    #[test]
+    fn bezier_curves() {
+        assert!((bezier(LINEAR, 0.25) - 0.25).abs() < 1e-3);
+        //Ease in starts slow
+        assert!(bezier([127, 0, 127, 127], 0.25) < 0.1);
+    }
+
+    #[test]
+    fn pmx_roundtrip() -> anyhow::Result<()> {
+        let skeleton = Skeleton::test(&[
+            ("n_hara_cp", BoneType::Type3, None, &[[0., 1., 0.]]),
+            ("kl_kubi", BoneType::Rotation, Some(0), &[[0., 0.5, 0.]]),
+            ("kl_te_l_wj", BoneType::Type1, Some(0), &[[0.5, 0.5, 0.]]),
+        ]);
+        let line = |a: f32, b: f32| {
+            let samples: Vec<f32> = (0..21).map(|i| a + (b - a) * i as f32 / 20.).collect();
+            FrameData::fit(0, &samples, 1e-5)
+        };
+        let mut anims = BTreeMap::new();
+        anims.insert(
+            Bone("n_hara_cp".into()),
+            Some(BoneAnim::PositionRotation {
+                position: (line(0., 0.8), FrameData::Pose(1.), line(0., 0.4)),
+                rotation: (FrameData::Pose(0.), line(0., 1.), FrameData::Pose(0.)),
+            }),
+        );
+        anims.insert(
+            Bone("kl_kubi".into()),
+            Some(BoneAnim::Rotation((
+                line(0.2, -0.2),
+                FrameData::Pose(0.),
+                FrameData::Pose(0.1),
+            ))),
+        );
+        //Skipped on export
+        let none = || (FrameData::None, FrameData::None, FrameData::None);
+        anims.insert(
+            Bone("kl_te_l_wj".into()),
+            Some(BoneAnim::Unk(none(), none())),
+        );
+        let mot = Motion { frames: 21, anims };
+
+        let map = BoneMap::pmx();
+        let vmd = mot.to_vmd(&skeleton, &map.inverse(), "ミク", 60., 0.08, 1e-5);
+        assert!(vmd.bones.iter().any(|k| k.name == "センター"));
+        let vmd = Vmd::read(&vmd.write())?;
+        assert_eq!(vmd.model, "ミク");
+        assert_eq!(vmd.bones.len(), 22);
+
+        let back = Motion::from_vmd(&vmd, &map, &skeleton, 60., 0.08, 1e-5)?;
+        assert_eq!(back.frames(), 21);
+        for path in &[
+            "n_hara_cp.position.x",
+            "n_hara_cp.position.z",
+            "n_hara_cp.rotation.y",
+            "kl_kubi.rotation.x",
+            "kl_kubi.rotation.z",
+        ] {
+            let path = ChannelPath::parse(path)?;
+            for f in 0..21 {
+                let a = mot.get(&path).unwrap().evaluate(f as f32).unwrap();
+                let b = back.get(&path).unwrap().evaluate(f as f32).unwrap();
+                assert!((a - b).abs() < 1e-3, "{} at {}: {} != {}", path, f, a, b);
+            }
+        }
+
+        let vmd = Vmd {
+            model: String::new(),
+            bones: vec![VmdBoneKey {
+                frame: 40000,
+                ..vmd.bones[0].clone()
+            }],
+        };
+        assert_eq!(
+            Motion::from_vmd(&vmd, &map, &skeleton, 60., 0.08, 1e-5),
+            Err(VmdError::TooLong(40000))
+        );
+        Ok(())
+    }
+}
@@ -0,0 +1,316 @@
+//!Motion XML in the layout exchanged by MikuMikuLibrary based editors.
+//!
+//!```xml
+//!<Motion FrameCount="120">
+//!  <Bone Name="n_hara_cp" Type="Type3">
+//!    <Position>
+//!      <X Type="None" />
+//!      <Y Type="Static"><Key Frame="0" Value="1" /></Y>
+//!      <Z Type="Linear"><Key Frame="0" Value="0" /><Key Frame="60" Value="0.5" /></Z>
+//!    </Position>
+//!    <Rotation>...</Rotation>
+//!  </Bone>
+//!</Motion>
+//!```
+//!
+//!Key sets map one to one onto [`FrameData`]: `Static` is a pose, `Linear` is Catmull-Rom and
+//!`Tangent` is Hermite. IK targets and the second vector of Type 1 bones go in `Unk`.
+use super::*;
+use diva_db::bone::BoneType;
+
+use core::fmt::Write;
+use core::str::FromStr;
+
+#[derive(Debug, Error)]
+pub enum XmlError {
+    #[error(transparent)]
+    Xml(#[from] roxmltree::Error),
+    #[error("Expected a `{0}` element")]
+    MissingElement(&'static str),
+    #[error("`{0}` is missing the `{1}` attribute")]
+    MissingAttribute(String, &'static str),
+    #[error("`{0}` is not a number")]
+    BadNumber(String),
+    #[error("Unknown key set type `{0}`")]
+    UnknownKeySet(String),
+    #[error("Unknown bone type `{0}`")]
+    UnknownBoneType(String),
+}
+
+fn bone_type(anim: &BoneAnim) -> &'static str {
+    match anim {
+        BoneAnim::Rotation(_) => "Rotation",
+        BoneAnim::Unk(_, _) => "Type1",
+        BoneAnim::Position(_) => "Position",
+        BoneAnim::PositionRotation { .. } => "Type3",
+        BoneAnim::RotationIk { .. } => "Type4",
+        BoneAnim::ArmIk { .. } => "Type5",
+        BoneAnim::LegIk { .. } => "Type6",
+    }
+}
+
+fn parse_bone_type(s: &str) -> Result<BoneType, XmlError> {
+    let ty = match s {
+        "Rotation" => BoneType::Rotation,
+        "Type1" => BoneType::Type1,
+        "Position" => BoneType::Position,
+        "Type3" => BoneType::Type3,
+        "Type4" => BoneType::Type4,
+        "Type5" => BoneType::Type5,
+        "Type6" => BoneType::Type6,
+        _ => return Err(XmlError::UnknownBoneType(s.to_string())),
+    };
+    Ok(ty)
+}
+
+///The `Position`, `Rotation` and `Unk` bindings of an animation
+fn bindings(anim: &BoneAnim) -> Vec<(&'static str, &Vec3)> {
+    match anim {
+        BoneAnim::Rotation(v) => vec![("Rotation", v)],
+        BoneAnim::Unk(u, v) => vec![("Position", u), ("Unk", v)],
+        BoneAnim::Position(v) => vec![("Position", v)],
+        BoneAnim::PositionRotation { position, rotation } => {
+            vec![("Position", position), ("Rotation", rotation)]
+        }
+        BoneAnim::RotationIk { target, rotation } | BoneAnim::ArmIk { target, rotation } => {
+            vec![("Unk", target), ("Rotation", rotation)]
+        }
+        BoneAnim::LegIk { position, target } => vec![("Unk", target), ("Position", position)],
+    }
+}
+
+fn write_key_set(out: &mut String, axis: &str, data: &FrameData) {
+    let (ty, keys): (_, Vec<(u16, f32, Option<f32>)>) = match data {
+        FrameData::None => ("None", vec![]),
+        FrameData::Pose(x) => ("Static", vec![(0, *x, None)]),
+        FrameData::CatmulRom(keys) => (
+            "Linear",
+            keys.iter().map(|k| (k.frame, k.value, None)).collect(),
+        ),
+        FrameData::Hermite(keys) => (
+            "Tangent",
+            keys.iter()
+                .map(|k| (k.frame, k.value, Some(k.interpolation)))
+                .collect(),
+        ),
+    };
+    if keys.is_empty() {
+        let _ = writeln!(out, "      <{} Type=\"{}\" />", axis, ty);
+        return;
+    }
+    let _ = writeln!(out, "      <{} Type=\"{}\">", axis, ty);
+    for (frame, value, tangent) in keys {
+        let _ = write!(out, "        <Key Frame=\"{}\" Value=\"{}\"", frame, value);
+        if let Some(tangent) = tangent {
+            let _ = write!(out, " Tangent=\"{}\"", tangent);
+        }
+        out.push_str(" />\n");
+    }
+    let _ = writeln!(out, "      </{}>", axis);
+}
+
+fn escape(s: &str) -> String {
+    s.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
+
+fn attribute<'x, T: FromStr>(
+    node: roxmltree::Node<'x, '_>,
+    name: &'static str,
+) -> Result<T, XmlError> {
+    let value = node
+        .attribute(name)
+        .ok_or_else(|| XmlError::MissingAttribute(node.tag_name().name().to_string(), name))?;
+    value
+        .parse()
+        .map_err(|_| XmlError::BadNumber(value.to_string()))
+}
+
+fn child<'x, 'i>(node: roxmltree::Node<'x, 'i>, name: &str) -> Option<roxmltree::Node<'x, 'i>> {
+    node.children()
+        .find(|n| n.is_element() && n.tag_name().name() == name)
+}
+
+fn read_key_set(node: Option<roxmltree::Node>) -> Result<FrameData, XmlError> {
+    let node = match node {
+        Some(node) => node,
+        None => return Ok(FrameData::None),
+    };
+    let keys = node
+        .children()
+        .filter(|n| n.is_element() && n.tag_name().name() == "Key");
+    let ty = node.attribute("Type").unwrap_or("None");
+    let data = match ty {
+        "None" => FrameData::None,
+        "Static" => {
+            let key = keys.clone().next().ok_or(XmlError::MissingElement("Key"))?;
+            FrameData::Pose(attribute(key, "Value")?)
+        }
+        "Linear" => FrameData::CatmulRom(
+            keys.map(|k| {
+                Ok(Keyframe {
+                    frame: attribute(k, "Frame")?,
+                    value: attribute(k, "Value")?,
+                    interpolation: (),
+                })
+            })
+            .collect::<Result<_, XmlError>>()?,
+        ),
+        "Tangent" => FrameData::Hermite(
+            keys.map(|k| {
+                Ok(Keyframe {
+                    frame: attribute(k, "Frame")?,
+                    value: attribute(k, "Value")?,
+                    interpolation: attribute(k, "Tangent")?,
+                })
+            })
+            .collect::<Result<_, XmlError>>()?,
+        ),
+        _ => return Err(XmlError::UnknownKeySet(ty.to_string())),
+    };
+    Ok(data)
+}
+
+fn read_binding(bone: roxmltree::Node, name: &str) -> Result<Vec3, XmlError> {
+    let node = child(bone, name);
+    let axis = |a| node.and_then(|n| child(n, a));
+    Ok((
+        read_key_set(axis("X"))?,
+        read_key_set(axis("Y"))?,
+        read_key_set(axis("Z"))?,
+    ))
+}
+
+impl<'a> Motion<'a> {
+    pub fn to_xml(&self) -> String {
+        let mut out = String::from("<?xml version=\"1.0\" encoding=\"utf-8\"?>\n");
+        let _ = writeln!(out, "<Motion FrameCount=\"{}\">", self.frames);
+        for (bone, anim) in &self.anims {
+            let name = escape(&bone.0);
+            let anim = match anim {
+                Some(anim) => anim,
+                None => {
+                    let _ = writeln!(out, "  <Bone Name=\"{}\" />", name);
+                    continue;
+                }
+            };
+            let _ = writeln!(
+                out,
+                "  <Bone Name=\"{}\" Type=\"{}\">",
+                name,
+                bone_type(anim)
+            );
+            for (binding, (x, y, z)) in bindings(anim) {
+                let _ = writeln!(out, "    <{}>", binding);
+                write_key_set(&mut out, "X", x);
+                write_key_set(&mut out, "Y", y);
+                write_key_set(&mut out, "Z", z);
+                let _ = writeln!(out, "    </{}>", binding);
+            }
+            out.push_str("  </Bone>\n");
+        }
+        out.push_str("</Motion>\n");
+        out
+    }
+
+    ///Reads motion XML. Bones without a `Type` are kept without an animation
+    pub fn from_xml(xml: &str) -> Result<Motion<'static>, XmlError> {
+        let doc = roxmltree::Document::parse(xml)?;
+        let root = doc.root_element();
+        if root.tag_name().name() != "Motion" {
+            return Err(XmlError::MissingElement("Motion"));
+        }
+        let frames = attribute(root, "FrameCount")?;
+        let mut anims = BTreeMap::new();
+        for bone in root
+            .children()
+            .filter(|n| n.is_element() && n.tag_name().name() == "Bone")
+        {
+            let name = bone
+                .attribute("Name")
+                .ok_or_else(|| XmlError::MissingAttribute("Bone".to_string(), "Name"))?;
+            let ty = match bone.attribute("Type") {
+                Some(ty) => parse_bone_type(ty)?,
+                None => {
+                    anims.insert(Bone(Cow::Owned(name.to_string())), None);
+                    continue;
+                }
+            };
+            let binding = |b| read_binding(bone, b);
+            let anim = match ty {
+                BoneType::Rotation => BoneAnim::Rotation(binding("Rotation")?),
+                BoneType::Type1 => BoneAnim::Unk(binding("Position")?, binding("Unk")?),
+                BoneType::Position => BoneAnim::Position(binding("Position")?),
+                BoneType::Type3 => BoneAnim::PositionRotation {
+                    position: binding("Position")?,
+                    rotation: binding("Rotation")?,
+                },
+                BoneType::Type4 => BoneAnim::RotationIk {
+                    target: binding("Unk")?,
+                    rotation: binding("Rotation")?,
+                },
+                BoneType::Type5 => BoneAnim::ArmIk {
+                    target: binding("Unk")?,
+                    rotation: binding("Rotation")?,
+                },
+                BoneType::Type6 => BoneAnim::LegIk {
+                    position: binding("Position")?,
+                    target: binding("Unk")?,
+                },
+            };
+            anims.insert(Bone(Cow::Owned(name.to_string())), Some(anim));
+        }
+        Ok(Motion { frames, anims })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn xml_roundtrip() -> anyhow::Result<()> {
+        let keys = FrameData::CatmulRom(vec![
+            Keyframe {
+                frame: 0,
+                value: 0.1,
+                interpolation: (),
+            },
+            Keyframe {
+                frame: 60,
+                value: 1. / 3.,
+                interpolation: (),
+            },
+        ]);
+        let hermite = FrameData::Hermite(vec![Keyframe {
+            frame: 5,
+            value: -2.5,
+            interpolation: 0.125,
+        }]);
+        let mut anims = BTreeMap::new();
+        anims.insert(
+            Bone("n_hara_cp".into()),
+            Some(BoneAnim::PositionRotation {
+                position: (FrameData::None, FrameData::Pose(1.), keys.clone()),
+                rotation: (hermite, FrameData::Pose(0.), FrameData::None),
+            }),
+        );
+        anims.insert(
+            Bone("c_kata_l".into()),
+            Some(BoneAnim::ArmIk {
+                target: (keys.clone(), FrameData::Pose(0.5), FrameData::None),
+                rotation: (FrameData::None, FrameData::None, keys),
+            }),
+        );
+        anims.insert(Bone("kl_kubi".into()), None);
+        let mot = Motion { frames: 61, anims };
+
+        let xml = mot.to_xml();
+        assert!(xml.contains("<Bone Name=\"c_kata_l\" Type=\"Type5\">\n    <Unk>"));
+        assert!(xml.contains("<Key Frame=\"5\" Value=\"-2.5\" Tangent=\"0.125\" />"));
+        assert_eq!(Motion::from_xml(&xml)?, mot);
+        Ok(())
+    }
+}